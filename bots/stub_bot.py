@@ -0,0 +1,31 @@
+#!/usr/bin/env python3
+"""Minimal external bot for comfy wars, see src/bot.rs for the protocol.
+
+Attacks the weakest enemy it can reach, otherwise moves to a random legal tile.
+
+    cargo run -- --bot "python3 bots/stub_bot.py"
+    cargo run -- tournament --red-bot "python3 bots/stub_bot.py"
+"""
+import json
+import random
+import sys
+
+
+def choose(observation):
+    actions = observation["legal_actions"]
+    hp = {(e["pos"]["x"], e["pos"]["y"]): e["hp"] for e in observation["visible_enemies"]}
+    attacks = [a for a in actions if a["attack"] is not None]
+    if attacks:
+        return min(attacks, key=lambda a: hp[(a["attack"]["x"], a["attack"]["y"])])
+    return random.choice(actions)
+
+
+def main():
+    for line in sys.stdin:
+        observation = json.loads(line)
+        reply = dict(choose(observation), id=observation["id"])
+        print(json.dumps(reply), flush=True)
+
+
+if __name__ == "__main__":
+    main()
@@ -49,6 +49,7 @@
             pkg-config # locate C dependencies
             alsa-lib # sound
             libxkbcommon # keyboard
+            udev # gamepads, only with `-F gamepad`
 
             vulkan-tools
             vulkan-headers
@@ -0,0 +1,311 @@
+/// decision making for computer controlled teams
+/// kept free of rendering so it can also run headless
+#[cfg(not(target_arch = "wasm32"))]
+use crate::bot::ExternalBot;
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetPriority {
+    /// attack whatever enemy is found first
+    First,
+    /// attack the enemy with the lowest hp
+    Weakest,
+}
+
+#[derive(Debug, Clone)]
+pub struct AiConfig {
+    pub name: String,
+    /// how strongly enemies pull units towards them
+    /// units ignore enemies which are further away (in movement cost)
+    /// and hold their position instead
+    pub engage_range: i32,
+    pub target_priority: TargetPriority,
+    /// units with this much hp or less get out of reach of enemies
+    /// instead of attacking, if they'd be killed where they stand
+    pub retreat_hp: i32,
+}
+
+impl Default for AiConfig {
+    fn default() -> Self {
+        Self {
+            name: "default".to_string(),
+            engage_range: 30,
+            target_priority: TargetPriority::First,
+            retreat_hp: 0,
+        }
+    }
+}
+
+impl AiConfig {
+    pub fn presets() -> Vec<AiConfig> {
+        vec![
+            AiConfig::default(),
+            AiConfig {
+                name: "focus".to_string(),
+                target_priority: TargetPriority::Weakest,
+                ..Default::default()
+            },
+            AiConfig {
+                name: "defensive".to_string(),
+                engage_range: 12,
+                target_priority: TargetPriority::Weakest,
+                retreat_hp: ATTACK_DAMAGE,
+            },
+        ]
+    }
+
+    pub fn preset(name: &str) -> Option<AiConfig> {
+        Self::presets().into_iter().find(|c| c.name == name)
+    }
+}
+
+/// damage each team could deal to each tile during its next turn
+pub struct InfluenceMaps {
+    pub blue: Grid<i32>,
+    pub red: Grid<i32>,
+}
+
+impl InfluenceMaps {
+    pub fn new(grids: &Grids, entities: &SlotMap<ActorKey, Actor>) -> Self {
+        let (w, h) = (grids.ground.width, grids.ground.height);
+        let mut blue = Grid::new(w, h, 0);
+        let mut red = Grid::new(w, h, 0);
+        for (index, actor) in entities.iter() {
+            let damage: Grid<i32> = unit_threat(grids, entities, index).mul_scalar(ATTACK_DAMAGE);
+            match actor.team {
+                Team::Blue => blue = blue.add(&damage),
+                Team::Red => red = red.add(&damage),
+            }
+        }
+        // more damage than a full unit can take doesn't make a tile any worse
+        blue.clamp_values(0, HP_MAX);
+        red.clamp_values(0, HP_MAX);
+        Self { blue, red }
+    }
+
+    /// damage `team` could deal
+    pub fn threat(&self, team: Team) -> &Grid<i32> {
+        match team {
+            Team::Blue => &self.blue,
+            Team::Red => &self.red,
+        }
+    }
+
+    /// positive where `team` could deal more damage than its enemies
+    pub fn balance(&self, team: Team) -> Grid<i32> {
+        let enemy: Grid<i32> = self.threat(team.other()).mul_scalar(-1);
+        self.threat(team).add(&enemy)
+    }
+}
+
+/// tiles the unit could attack during its next turn, 1 or 0
+pub fn unit_threat(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    index: ActorKey,
+) -> Grid<i32> {
+    reach_threat(grids, &Reachability::new(grids, entities, index))
+}
+
+/// tiles next to any tile the unit can stop on, 1 or 0
+pub fn reach_threat(grids: &Grids, reach: &Reachability) -> Grid<i32> {
+    let mut threat = Grid::new(grids.ground.width, grids.ground.height, 0);
+    for pos in reach.stoppable_tiles() {
+        for target in grids.ground.neighbors(pos, grids.topology) {
+            threat[target] = 1;
+        }
+    }
+    threat
+}
+
+/// result of planning the movement of a single unit
+pub struct AiMove {
+    pub path: Vec<IVec2>,
+}
+
+impl AiMove {
+    /// where the unit ends up after following the path
+    pub fn destination(&self, entities: &SlotMap<ActorKey, Actor>, index: ActorKey) -> IVec2 {
+        self.path.last().copied().unwrap_or(entities[index].pos)
+    }
+}
+
+/// moves as close to the nearest enemy within engage range as possible
+pub fn plan_move(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    index: ActorKey,
+    config: &AiConfig,
+) -> AiMove {
+    let team = entities[index].team;
+    let enemy_positions = entities
+        .iter()
+        .filter(|(_i, a)| a.team != team)
+        .map(|(_i, a)| a.pos)
+        .collect_vec();
+    let path = path_towards(
+        grids,
+        entities,
+        index,
+        &enemy_positions,
+        Some(config.engage_range),
+    );
+    AiMove { path }
+}
+
+/// moves to the stoppable tile where the least enemy damage can arrive,
+/// None if the unit would survive staying where it is
+pub fn plan_retreat(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    index: ActorKey,
+    influence: &InfluenceMaps,
+) -> Option<AiMove> {
+    let actor = &entities[index];
+    let danger = influence.threat(actor.team.other());
+    if danger[actor.pos] < actor.hp {
+        return None;
+    }
+    let reach = Reachability::new(grids, entities, index);
+    let support = influence.threat(actor.team);
+    // prefer staying close to allies that could strike back
+    let destination = reach
+        .stoppable_tiles()
+        .min_by_key(|pos| (danger[*pos], -support[*pos], reach.moves.cost[*pos]))?;
+    Some(AiMove {
+        path: reach.path_to(destination)?,
+    })
+}
+
+/// true if any enemy can be attacked this turn
+pub fn can_attack(grids: &Grids, entities: &SlotMap<ActorKey, Actor>, index: ActorKey) -> bool {
+    Reachability::new(grids, entities, index).has_targets()
+}
+
+/// picks which enemy to attack when standing at `from`, if any
+pub fn choose_target(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    index: ActorKey,
+    from: IVec2,
+    config: &AiConfig,
+) -> Option<(ActorKey, IVec2)> {
+    let enemies = enemies_in_range_from(grids, entities, index, from);
+    match config.target_priority {
+        TargetPriority::First => enemies.first().copied(),
+        TargetPriority::Weakest => enemies
+            .into_iter()
+            .min_by_key(|(enemy, _)| entities[*enemy].hp),
+    }
+}
+
+/// complete decision of the built-in ai for a single unit
+pub fn decide(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    index: ActorKey,
+    config: &AiConfig,
+) -> UnitAction {
+    // damage dealt now is worth more than a unit that may survive,
+    // so only units which can't attack anyway retreat
+    let retreat = if entities[index].hp <= config.retreat_hp && !can_attack(grids, entities, index)
+    {
+        let influence = InfluenceMaps::new(grids, entities);
+        plan_retreat(grids, entities, index, &influence)
+    } else {
+        None
+    };
+    let plan = retreat.unwrap_or_else(|| plan_move(grids, entities, index, config));
+    let move_to = plan.destination(entities, index);
+    let attack = choose_target(grids, entities, index, move_to, config).map(|(_, pos)| pos);
+    UnitAction { move_to, attack }
+}
+
+/// who makes the decisions for a team
+/// an external bot if there is one, with the built-in ai as fallback
+#[derive(Default)]
+pub struct Controller {
+    pub config: AiConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub bot: Option<ExternalBot>,
+}
+
+impl Controller {
+    pub fn decide(
+        &mut self,
+        grids: &Grids,
+        entities: &SlotMap<ActorKey, Actor>,
+        index: ActorKey,
+    ) -> UnitAction {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(action) = self
+            .bot
+            .as_mut()
+            .and_then(|bot| bot.decide(grids, entities, index))
+        {
+            return action;
+        }
+        decide(grids, entities, index, &self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ascii_map::test_board;
+
+    #[test]
+    fn threat_adds_up_per_team() {
+        let (grids, entities) = test_board(
+            "
+            ....................
+            rr.................b
+            ....................
+
+            r: red_infantry
+            b: blue_infantry
+            ",
+        );
+        let influence = InfluenceMaps::new(&grids, &entities);
+
+        // 4 moves on plain ground and one more tile to attack
+        assert_eq!(2 * ATTACK_DAMAGE, influence.red[(3, 1)]);
+        assert_eq!(ATTACK_DAMAGE, influence.red[(6, 1)]);
+        assert_eq!(0, influence.red[(7, 1)]);
+        assert_eq!(ATTACK_DAMAGE, influence.blue[(14, 1)]);
+        assert_eq!(0, influence.blue[(3, 1)]);
+        assert_eq!(2 * ATTACK_DAMAGE, influence.balance(Team::Red)[(3, 1)]);
+        assert_eq!(-ATTACK_DAMAGE, influence.balance(Team::Red)[(14, 1)]);
+    }
+
+    #[test]
+    fn weak_units_retreat() {
+        let (grids, mut entities) = test_board(
+            "
+            ......r.............
+            ......r...b.........
+            ....................
+
+            r: red_infantry
+            b: blue_infantry
+            ",
+        );
+        let weak = actor_at_pos(&entities, ivec2(10, 1)).unwrap();
+        entities[weak].hp = ATTACK_DAMAGE;
+        let influence = InfluenceMaps::new(&grids, &entities);
+
+        let retreat = plan_retreat(&grids, &entities, weak, &influence).unwrap();
+        assert_eq!(0, influence.red[retreat.destination(&entities, weak)]);
+
+        // attacking still beats running away
+        let config = AiConfig::preset("defensive").unwrap();
+        let action = decide(&grids, &entities, weak, &config);
+        assert!(action.attack.is_some());
+
+        // out of reach, nothing to run from
+        entities[weak].pos = ivec2(15, 1);
+        let influence = InfluenceMaps::new(&grids, &entities);
+        assert!(plan_retreat(&grids, &entities, weak, &influence).is_none());
+    }
+}
@@ -0,0 +1,422 @@
+/// plain text maps for tests and bug reports
+///
+/// ```text
+/// ..~~..
+/// .a=^b.
+/// ..~~..
+///
+/// a: blue_tank hp=4
+/// b: red_infantry moved on=^
+/// topology: hex
+/// ```
+///
+/// the map comes first, one char per tile: `.` ground, `~` water, `=` street,
+/// `^` forest, `#` street on water and `%` forest on water
+/// after a blank line the legend, every other char on the map is a unit:
+/// `<char>: <unit name> [team=blue|red] [hp=<hp>] [moved] [on=<tile>]`, hp goes from 1 to 10
+/// a legend char can be used for any number of units, but only be defined once
+use crate::dijkstra::UNREACHABLE;
+use crate::*;
+
+const TILES: [(char, GroundType, TerrainType); 6] = [
+    ('.', GroundType::Ground, TerrainType::None),
+    ('~', GroundType::Water, TerrainType::None),
+    ('=', GroundType::Ground, TerrainType::Street),
+    ('^', GroundType::Ground, TerrainType::Forest),
+    ('#', GroundType::Water, TerrainType::Street),
+    ('%', GroundType::Water, TerrainType::Forest),
+];
+
+/// chars for units when writing a map, in this order
+const UNIT_CHARS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+fn tile(c: char) -> Option<(GroundType, TerrainType)> {
+    TILES
+        .iter()
+        .find(|(tile, _, _)| *tile == c)
+        .map(|(_, ground, terrain)| (*ground, *terrain))
+}
+
+fn tile_char(ground: GroundType, terrain: TerrainType) -> char {
+    TILES
+        .iter()
+        .find(|(_, g, t)| *g == ground && *t == terrain)
+        .map(|(c, _, _)| *c)
+        .expect("every combination has a char")
+}
+
+/// a line of the legend, what to put where its char is on the map
+#[derive(Debug, Clone, PartialEq)]
+struct UnitLegend {
+    name: String,
+    team: Option<Team>,
+    hp: i32,
+    moved: bool,
+    on: char,
+}
+
+impl UnitLegend {
+    fn parse(text: &str) -> Result<Self> {
+        let mut words = text.split_whitespace();
+        let name = words
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("a unit needs a name"))?;
+        let mut legend = UnitLegend {
+            name: name.to_string(),
+            team: None,
+            hp: HP_MAX,
+            moved: false,
+            on: '.',
+        };
+        for word in words {
+            match word.split_once('=') {
+                Some(("team", team)) => {
+                    legend.team = Some(match team.to_lowercase().as_str() {
+                        "blue" => Team::Blue,
+                        "red" => Team::Red,
+                        _ => anyhow::bail!("unknown team '{}'", team),
+                    })
+                }
+                Some(("hp", hp)) => {
+                    legend.hp = hp.parse()?;
+                    if !(1..=HP_MAX).contains(&legend.hp) {
+                        anyhow::bail!("hp {} is not between 1 and {}", legend.hp, HP_MAX);
+                    }
+                }
+                Some(("on", on)) => {
+                    let mut chars = on.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) if tile(c).is_some() => legend.on = c,
+                        _ => anyhow::bail!("'{}' is no tile", on),
+                    }
+                }
+                None if word == "moved" => legend.moved = true,
+                _ => anyhow::bail!("unknown unit property '{}'", word),
+            }
+        }
+        Ok(legend)
+    }
+
+    fn to_line(&self, c: char) -> String {
+        let mut line = format!("{}: {}", c, self.name);
+        if let Some(team) = self.team {
+            line += &format!(" team={}", format!("{:?}", team).to_lowercase());
+        }
+        if self.hp != HP_MAX {
+            line += &format!(" hp={}", self.hp);
+        }
+        if self.moved {
+            line += " moved";
+        }
+        if self.on != '.' {
+            line += &format!(" on={}", self.on);
+        }
+        line
+    }
+}
+
+impl GameMap {
+    /// reads a map in the text format described at the top of this file,
+    /// leading whitespace of every line is ignored so maps can be indented in code
+    pub fn from_ascii(text: &str, entity_defs: &HashMap<String, EntityDef>) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .skip_while(|line| line.is_empty());
+        let rows = lines
+            .by_ref()
+            .take_while(|line| !line.is_empty())
+            .map(|line| line.chars().collect_vec())
+            .collect_vec();
+        let Some(width) = rows.first().map(Vec::len) else {
+            anyhow::bail!("the map is empty");
+        };
+        if let Some(y) = rows.iter().position(|row| row.len() != width) {
+            anyhow::bail!("row {} is not {} tiles wide", y, width);
+        }
+
+        let mut topology = Topology::default();
+        let mut legend = HashMap::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let Some((key, value)) = line.split_once(':') else {
+                anyhow::bail!("legend line '{}' has no ':'", line);
+            };
+            let key = key.trim();
+            if key == "topology" {
+                topology = Topology::ALL
+                    .into_iter()
+                    .find(|t| format!("{:?}", t).eq_ignore_ascii_case(value.trim()))
+                    .ok_or_else(|| anyhow::anyhow!("unknown topology '{}'", value.trim()))?;
+                continue;
+            }
+            let mut chars = key.chars();
+            let (Some(c), None) = (chars.next(), chars.next()) else {
+                anyhow::bail!("legend key '{}' is not a single char", key);
+            };
+            if tile(c).is_some() {
+                anyhow::bail!("'{}' is a tile, it can't be a unit", c);
+            }
+            let unit = UnitLegend::parse(value).map_err(|e| e.context(format!("unit '{}'", c)))?;
+            if !entity_defs.contains_key(&unit.name) {
+                anyhow::bail!("unknown unit '{}'", unit.name);
+            }
+            if legend.insert(c, unit).is_some() {
+                anyhow::bail!("unit '{}' is in the legend twice", c);
+            }
+        }
+
+        let (w, h) = (width as i32, rows.len() as i32);
+        let mut grids = Grids {
+            ground: Grid::new(w, h, GroundType::Ground),
+            terrain: Grid::new(w, h, TerrainType::None),
+            topology,
+        };
+        let mut actors = vec![];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.iter().enumerate() {
+                let pos = ivec2(x as i32, y as i32);
+                let on = match legend.get(c) {
+                    Some(unit) => {
+                        let mut actor = Actor::from_def(&unit.name, &entity_defs[&unit.name], pos);
+                        actor.team = unit.team.unwrap_or(actor.team);
+                        actor.hp = unit.hp;
+                        actor.has_moved = unit.moved;
+                        actors.push(actor);
+                        unit.on
+                    }
+                    None => *c,
+                };
+                let (ground, terrain) =
+                    tile(on).ok_or_else(|| anyhow::anyhow!("unknown tile '{}' at {}", on, pos))?;
+                grids.ground[pos] = ground;
+                grids.terrain[pos] = terrain;
+            }
+        }
+        Ok(Self { grids, actors })
+    }
+
+    /// writes the map in the text format, units get a char each in order of appearance
+    /// the team is always written, so the map reads the same with other unit definitions
+    pub fn to_ascii(&self) -> Result<String> {
+        let grids = &self.grids;
+        let mut legend: Vec<UnitLegend> = vec![];
+        let mut map = String::new();
+        for y in 0..grids.ground.height {
+            for x in 0..grids.ground.width {
+                let pos = ivec2(x, y);
+                let on = tile_char(grids.ground[pos], grids.terrain[pos]);
+                let Some(actor) = self.actors.iter().find(|a| a.pos == pos) else {
+                    map.push(on);
+                    continue;
+                };
+                let unit = UnitLegend {
+                    name: actor.sprite_name.clone(),
+                    team: Some(actor.team),
+                    hp: actor.hp,
+                    moved: actor.has_moved,
+                    on,
+                };
+                let index = match legend.iter().position(|u| *u == unit) {
+                    Some(index) => index,
+                    None => {
+                        legend.push(unit);
+                        legend.len() - 1
+                    }
+                };
+                let c = UNIT_CHARS
+                    .chars()
+                    .nth(index)
+                    .ok_or_else(|| anyhow::anyhow!("too many different units for a text map"))?;
+                map.push(c);
+            }
+            map.push('\n');
+        }
+
+        if !legend.is_empty() || grids.topology != Topology::default() {
+            map.push('\n');
+        }
+        for (unit, c) in legend.iter().zip(UNIT_CHARS.chars()) {
+            map += &unit.to_line(c);
+            map.push('\n');
+        }
+        if grids.topology != Topology::default() {
+            map += &format!("topology: {:?}\n", grids.topology).to_lowercase();
+        }
+        Ok(map)
+    }
+}
+
+/// a board written as a text map, for tests
+#[cfg(test)]
+pub fn test_board(text: &str) -> (Grids, SlotMap<ActorKey, Actor>) {
+    let map = GameMap::from_ascii(text, &entity_defs().unwrap()).unwrap();
+    let mut entities = SlotMap::with_key();
+    for actor in map.actors {
+        entities.insert(actor);
+    }
+    (map.grids, entities)
+}
+
+/// the board as fixed width text with coordinates, for bug reports and snapshot tests
+/// tiles use the chars of text maps, units show team, type and hp like `bT10`
+/// `overlay` values, e.g. dijkstra costs, are shown instead of the tiles,
+/// except where they are negative or unreachable
+pub fn dump_board(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    overlay: Option<&Grid<i32>>,
+) -> String {
+    let (w, h) = (grids.ground.width, grids.ground.height);
+    let cells = Grid::filled_with(w, h, |x, y| {
+        let pos = ivec2(x, y);
+        if let Some(unit) = actor_at_pos(entities, pos) {
+            let actor = &entities[unit];
+            let team = match actor.team {
+                Team::Blue => 'b',
+                Team::Red => 'r',
+            };
+            let unit_type = format!("{:?}", actor.unit_type).remove(0);
+            return format!("{}{}{}", team, unit_type, actor.hp);
+        }
+        match overlay.filter(|o| o.is_valid(pos)).map(|o| o[pos]) {
+            Some(value) if value >= 0 && value != UNREACHABLE => value.to_string(),
+            _ => tile_char(grids.ground[pos], grids.terrain[pos]).to_string(),
+        }
+    });
+    let width = cells
+        .iter_values()
+        .map(String::len)
+        .chain([(w - 1).to_string().len()])
+        .max()
+        .unwrap_or(1);
+    let label = (h - 1).max(0).to_string().len();
+
+    let mut text = " ".repeat(label);
+    for x in 0..w {
+        text += &format!(" {:>width$}", x);
+    }
+    for y in 0..h {
+        text += &format!("\n{:>label$}", y);
+        for x in 0..w {
+            text += &format!(" {:>width$}", cells[(x, y)]);
+        }
+    }
+    text.push('\n');
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_tiles_and_units() {
+        let map = GameMap::from_ascii(
+            "
+            ..~~..
+            .a=^b.
+            .a~#..
+
+            a: blue_tank hp=4
+            b: red_infantry moved on=^
+            ",
+            &entity_defs().unwrap(),
+        )
+        .unwrap();
+        let grids = &map.grids;
+        assert_eq!((6, 3), (grids.ground.width, grids.ground.height));
+        assert_eq!(GroundType::Water, grids.ground[(2, 0)]);
+        assert_eq!(TerrainType::Street, grids.terrain[(2, 1)]);
+        assert_eq!(TerrainType::Forest, grids.terrain[(3, 1)]);
+        assert_eq!(
+            (GroundType::Water, TerrainType::Street),
+            (grids.ground[(3, 2)], grids.terrain[(3, 2)])
+        );
+
+        assert_eq!(3, map.actors.len());
+        let tanks = map
+            .actors
+            .iter()
+            .filter(|a| a.sprite_name == "blue_tank")
+            .collect_vec();
+        assert_eq!(2, tanks.len());
+        assert!(tanks.iter().all(|a| a.hp == 4 && a.team == Team::Blue));
+        let infantry = map.actors.iter().find(|a| a.pos == ivec2(4, 1)).unwrap();
+        assert_eq!(Team::Red, infantry.team);
+        assert!(infantry.has_moved);
+        assert_eq!(TerrainType::Forest, grids.terrain[(4, 1)]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let defs = entity_defs().unwrap();
+        let text = "\
+            .a.~\n\
+            ^b=#\n\
+            \n\
+            a: red_tank team=blue hp=3\n\
+            b: blue_infantry team=blue moved on=^\n\
+            topology: hex\n";
+        let map = GameMap::from_ascii(text, &defs).unwrap();
+        assert_eq!(Team::Blue, map.actors[0].team);
+        assert_eq!(Topology::Hex, map.grids.topology);
+        assert_eq!(text, map.to_ascii().unwrap());
+
+        // the built-in map survives a trip through text
+        let level = GameMap::load("assets/comfy_wars.ldtk").unwrap();
+        let text = level.to_ascii().unwrap();
+        let loaded = GameMap::from_ascii(&text, &defs).unwrap();
+        assert_eq!(text, loaded.to_ascii().unwrap());
+        assert_eq!(level.actors.len(), loaded.actors.len());
+    }
+
+    #[test]
+    fn errors_name_the_problem() {
+        let defs = entity_defs().unwrap();
+        let error = |text| GameMap::from_ascii(text, &defs).unwrap_err().to_string();
+        assert!(error("").contains("empty"));
+        assert!(error("...\n..").contains("row 1"));
+        assert!(error(".x.").contains("'x'"));
+        assert!(error(".a.\n\na: dragon").contains("dragon"));
+        assert!(error(".a.\n\na: red_tank hp=lots").contains("unit 'a'"));
+        for hp in ["0", "-3", "11"] {
+            let text = format!(".a.\n\na: red_tank hp={}", hp);
+            let e = GameMap::from_ascii(&text, &defs).unwrap_err();
+            assert!(format!("{:#}", e).contains("not between 1 and"), "{:#}", e);
+        }
+        assert!(error(".a.\n\na: red_tank\na: blue_tank").contains("'a' is in the legend twice"));
+        assert!(error("...\n\ntopology: cube").contains("cube"));
+    }
+
+    #[test]
+    fn dump_with_move_costs() {
+        let map = GameMap::from_ascii(
+            "
+            .a~
+            .=^
+
+            a: blue_infantry hp=7
+            ",
+            &entity_defs().unwrap(),
+        )
+        .unwrap();
+        let mut entities = SlotMap::with_key();
+        let unit = entities.insert(map.actors[0].clone());
+        let board = dump_board(&map.grids, &entities, None);
+        assert_eq!(
+            "    0   1   2\n\
+             0   . bI7   ~\n\
+             1   .   =   ^\n",
+            board
+        );
+
+        let reach = Reachability::new(&map.grids, &entities, unit);
+        let costs = reach.moves.reached_costs();
+        assert_eq!(
+            "    0   1   2\n\
+             0   2 bI7   ~\n\
+             1   3   1   4\n",
+            dump_board(&map.grids, &entities, Some(&costs))
+        );
+    }
+}
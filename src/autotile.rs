@@ -0,0 +1,213 @@
+/// derives tiles from the ground and terrain grids, like ldtk's auto layers do
+/// for maps which are edited or generated at runtime
+/// every tile is a position in the tilemap, the source rect of its sprite
+use crate::*;
+
+/// offsets of the orthogonal neighbors, n e s w
+const SIDES: [IVec2; 4] = [ivec2(0, -1), ivec2(1, 0), ivec2(0, 1), ivec2(-1, 0)];
+/// offsets of the diagonal neighbors, nw ne se sw
+const CORNERS: [IVec2; 4] = [ivec2(-1, -1), ivec2(1, -1), ivec2(1, 1), ivec2(-1, 1)];
+
+/// a tile to draw, `pos` on the map and `src` in the tilemap, both in tiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoTile {
+    pub pos: IVec2,
+    pub src: IVec2,
+}
+
+/// bit i is set if the neighbor at `offsets[i]` matches
+/// outside of the map always matches, so water runs off the edge without a shore
+fn mask<T: Clone>(
+    grid: &Grid<T>,
+    pos: IVec2,
+    offsets: &[IVec2; 4],
+    same: impl Fn(&T) -> bool,
+) -> u8 {
+    offsets.iter().enumerate().fold(0, |mask, (i, offset)| {
+        let other = pos + *offset;
+        if !grid.is_valid(other) || same(&grid[other]) {
+            mask | 1 << i
+        } else {
+            mask
+        }
+    })
+}
+
+/// cheap and stable noise so the same map always gets the same variants
+fn pos_hash(pos: IVec2) -> u32 {
+    let h = (pos.x as u32).wrapping_mul(0x9e37_79b1) ^ (pos.y as u32).wrapping_mul(0x85eb_ca77);
+    (h ^ h >> 15).wrapping_mul(0xc2b2_ae3d) >> 16
+}
+
+/// one tile per cell, water gets shores where it meets the ground
+pub fn ground_tiles(ground: &Grid<GroundType>) -> Vec<AutoTile> {
+    ground
+        .iter_coords()
+        .map(|(pos, kind)| {
+            let src = match kind {
+                GroundType::Ground => match pos_hash(pos) % 16 {
+                    0 | 1 => ivec2(16, 0),
+                    2 => ivec2(32, 0),
+                    _ => ivec2(0, 0),
+                },
+                GroundType::Water => {
+                    let water = |g: &GroundType| *g == GroundType::Water;
+                    water_tile(
+                        mask(ground, pos, &SIDES, water),
+                        mask(ground, pos, &CORNERS, water),
+                    )
+                }
+            };
+            AutoTile { pos, src }
+        })
+        .collect()
+}
+
+/// the lake in the tilemap, shores on the sides without water
+/// and inner corners where only a diagonal neighbor is land
+fn water_tile(sides: u8, corners: u8) -> IVec2 {
+    match sides {
+        0b1110 => ivec2(16, 16),
+        0b0110 => ivec2(0, 16),
+        0b1100 => ivec2(32, 16),
+        0b0111 => ivec2(0, 32),
+        0b1101 => ivec2(32, 32),
+        0b1011 => ivec2(16, 48),
+        0b0011 => ivec2(0, 48),
+        0b1001 => ivec2(32, 48),
+        0b1111 => match corners {
+            0b1110 => ivec2(0, 80),
+            0b1101 => ivec2(16, 80),
+            0b1011 => ivec2(32, 80),
+            0b0111 => ivec2(48, 80),
+            _ => ivec2(16, 32),
+        },
+        // the tilemap has no narrow water, open water is the closest match
+        _ => ivec2(16, 32),
+    }
+}
+
+/// tiles only where there is terrain, streets connect to their neighbors
+pub fn terrain_tiles(terrain: &Grid<TerrainType>) -> Vec<AutoTile> {
+    terrain
+        .iter_coords()
+        .filter_map(|(pos, kind)| {
+            let src = match kind {
+                TerrainType::None => return None,
+                TerrainType::Street => {
+                    // streets end at the map edge instead of running off it
+                    let sides = SIDES.iter().enumerate().fold(0, |mask, (i, offset)| {
+                        let other = pos + *offset;
+                        if terrain.is_valid(other) && terrain[other] == TerrainType::Street {
+                            mask | 1 << i
+                        } else {
+                            mask
+                        }
+                    });
+                    street_tile(sides)
+                }
+                TerrainType::Forest => {
+                    let forest = |t: &TerrainType| *t == TerrainType::Forest;
+                    forest_tile(mask(terrain, pos, &SIDES, forest))
+                }
+            };
+            Some(AutoTile { pos, src })
+        })
+        .collect()
+}
+
+/// straight pieces, ends and the corners and junctions of the plaza
+fn street_tile(sides: u8) -> IVec2 {
+    match sides {
+        0b0000 => ivec2(0, 96),
+        0b0010 => ivec2(16, 96),
+        0b1010 => ivec2(32, 96),
+        0b1000 => ivec2(48, 96),
+        0b0100 => ivec2(0, 112),
+        0b0101 => ivec2(0, 128),
+        0b0001 => ivec2(0, 144),
+        0b0110 => ivec2(16, 112),
+        0b1110 => ivec2(32, 112),
+        0b1100 => ivec2(48, 112),
+        0b0111 => ivec2(16, 128),
+        0b1111 => ivec2(32, 128),
+        0b1101 => ivec2(48, 128),
+        0b0011 => ivec2(16, 144),
+        0b1011 => ivec2(32, 144),
+        0b1001 => ivec2(48, 144),
+        _ => unreachable!("street mask has 4 bits"),
+    }
+}
+
+/// dense trees at the edges of a forest, single trees where it runs on
+/// above and below, which breaks up larger forests
+fn forest_tile(sides: u8) -> IVec2 {
+    if sides & 0b0101 == 0b0101 {
+        ivec2(64, 80)
+    } else {
+        ivec2(64, 96)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn src_at(tiles: &[AutoTile], pos: (i32, i32)) -> IVec2 {
+        tiles.iter().find(|t| t.pos == pos.into()).unwrap().src
+    }
+
+    #[test]
+    fn water_matches_the_ldtk_tiles() {
+        let ldtk: LDTK =
+            DeJson::deserialize_json(&std::fs::read_to_string("assets/comfy_wars.ldtk").unwrap())
+                .unwrap();
+        let level = &ldtk.levels[0];
+        let grids = grids_from_level(level).unwrap();
+        let tiles = ground_tiles(&grids.ground);
+        assert_eq!(grids.ground.len(), tiles.len());
+
+        let layer = level.layers.iter().find(|l| l.id == "groundgrid").unwrap();
+        for baked in &layer.auto_tiles {
+            let pos = ivec2(baked.px[0] as i32, baked.px[1] as i32) / GRIDSIZE;
+            if grids.ground[pos] == GroundType::Water {
+                let src = ivec2(baked.src[0], baked.src[1]);
+                assert_eq!(src, src_at(&tiles, pos.into()), "{}", pos);
+            }
+        }
+    }
+
+    #[test]
+    fn street_junctions() {
+        let mut terrain = Grid::new(5, 3, TerrainType::None);
+        for x in 0..5 {
+            terrain[(x, 1)] = TerrainType::Street;
+        }
+        terrain[(2, 0)] = TerrainType::Street;
+        let tiles = terrain_tiles(&terrain);
+        assert_eq!(6, tiles.len());
+        // the left end doesn't connect to outside of the map
+        assert_eq!(ivec2(16, 96), src_at(&tiles, (0, 1)));
+        assert_eq!(ivec2(32, 96), src_at(&tiles, (1, 1)));
+        assert_eq!(ivec2(32, 144), src_at(&tiles, (2, 1)));
+        assert_eq!(ivec2(48, 96), src_at(&tiles, (4, 1)));
+        assert_eq!(ivec2(0, 112), src_at(&tiles, (2, 0)));
+    }
+
+    #[test]
+    fn forest_edges() {
+        let mut terrain = Grid::new(2, 5, TerrainType::None);
+        for y in 1..4 {
+            terrain[(0, y)] = TerrainType::Forest;
+        }
+        terrain[(1, 0)] = TerrainType::Forest;
+        terrain[(1, 1)] = TerrainType::Forest;
+        let tiles = terrain_tiles(&terrain);
+        assert_eq!(ivec2(64, 96), src_at(&tiles, (0, 1)));
+        assert_eq!(ivec2(64, 80), src_at(&tiles, (0, 2)));
+        assert_eq!(ivec2(64, 96), src_at(&tiles, (0, 3)));
+        // the map edge counts as more forest, so the column runs off the top
+        assert_eq!(ivec2(64, 80), src_at(&tiles, (1, 0)));
+        assert_eq!(ivec2(64, 96), src_at(&tiles, (1, 1)));
+    }
+}
@@ -0,0 +1,372 @@
+/// external bots: a team's decisions are delegated to another process
+///
+/// the protocol is line based json over stdin/stdout
+/// for every unit that has to act the game writes one observation line:
+/// `{"id": 3, "team": "Red", "unit": {...}, "grids": {...}, "allies": [...],
+///   "visible_enemies": [...], "legal_actions": [{"move_to": {"x": 1, "y": 2}, "attack": null}, ...]}`
+/// and the bot answers with one line containing one of the legal actions:
+/// `{"id": 3, "move_to": {"x": 1, "y": 2}, "attack": {"x": 1, "y": 3}}`
+///
+/// `id` should be echoed back so late answers can be told apart.
+/// If the bot answers too late, with garbage or with an illegal action
+/// the built-in ai decides instead.
+/// See `bots/stub_bot.py` for a minimal bot.
+///
+/// bots are separate processes, so there are none on wasm
+use crate::util::*;
+use crate::*;
+use slotmap::Key;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_BOT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Serialize)]
+pub struct UnitInfo {
+    pub id: u64,
+    #[serde(with = "IVec2Proxy")]
+    pub pos: IVec2,
+    pub team: Team,
+    pub unit_type: UnitType,
+    pub hp: i32,
+    pub has_moved: bool,
+}
+
+impl UnitInfo {
+    pub fn new(key: ActorKey, actor: &Actor) -> Self {
+        Self {
+            id: key.data().as_ffi(),
+            pos: actor.pos,
+            team: actor.team,
+            unit_type: actor.unit_type,
+            hp: actor.hp,
+            has_moved: actor.has_moved,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Observation<'a> {
+    pub id: u64,
+    pub team: Team,
+    pub unit: UnitInfo,
+    pub grids: &'a Grids,
+    pub allies: Vec<UnitInfo>,
+    pub visible_enemies: Vec<UnitInfo>,
+    pub legal_actions: Vec<UnitAction>,
+}
+
+impl<'a> Observation<'a> {
+    pub fn new(
+        id: u64,
+        grids: &'a Grids,
+        entities: &SlotMap<ActorKey, Actor>,
+        me: ActorKey,
+    ) -> Self {
+        let team = entities[me].team;
+        let others = |same_team: bool| {
+            entities
+                .iter()
+                .filter(|(k, a)| *k != me && (a.team == team) == same_team)
+                .map(|(k, a)| UnitInfo::new(k, a))
+                .collect_vec()
+        };
+        Self {
+            id,
+            team,
+            unit: UnitInfo::new(me, &entities[me]),
+            grids,
+            allies: others(true),
+            // there is no fog of war (yet), so every enemy is visible
+            visible_enemies: others(false),
+            legal_actions: legal_actions(grids, entities, me),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Reply {
+    id: Option<u64>,
+    #[serde(flatten)]
+    action: UnitAction,
+}
+
+/// an observation sent to the bot that waits for its answer
+pub struct Question {
+    id: u64,
+    legal_actions: Vec<UnitAction>,
+    deadline: Instant,
+}
+
+pub enum BotReply {
+    Waiting,
+    /// None if the bot failed to give a legal answer in time
+    Done(Option<UnitAction>),
+}
+
+pub struct ExternalBot {
+    command: String,
+    child: Child,
+    stdin: ChildStdin,
+    replies: Receiver<String>,
+    timeout: Duration,
+    next_id: u64,
+    alive: bool,
+}
+
+impl ExternalBot {
+    /// starts the bot, `command` is split on whitespace into program and arguments
+    pub fn spawn(command: &str, timeout: Duration) -> Result<Self> {
+        let mut parts = command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("empty bot command"))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        // reading happens on its own thread so we can wait with a timeout
+        let (sender, replies) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            command: command.to_string(),
+            child,
+            stdin,
+            replies,
+            timeout,
+            next_id: 0,
+            alive: true,
+        })
+    }
+
+    /// asks the bot what the unit should do and waits for the answer
+    /// returns None if the bot failed to give a legal answer in time
+    pub fn decide(
+        &mut self,
+        grids: &Grids,
+        entities: &SlotMap<ActorKey, Actor>,
+        me: ActorKey,
+    ) -> Option<UnitAction> {
+        let question = self.ask(grids, entities, me)?;
+        loop {
+            let remaining = question.deadline.saturating_duration_since(Instant::now());
+            let reply = match self.replies.recv_timeout(remaining) {
+                Ok(reply) => Ok(reply),
+                Err(RecvTimeoutError::Timeout) => Err(TryRecvError::Empty),
+                Err(RecvTimeoutError::Disconnected) => Err(TryRecvError::Disconnected),
+            };
+            if let Some(BotReply::Done(action)) = self.answer(&question, reply) {
+                return action;
+            }
+        }
+    }
+
+    /// sends the observation without waiting, `poll` gets the answer
+    /// returns None if the bot can't be reached
+    pub fn ask(
+        &mut self,
+        grids: &Grids,
+        entities: &SlotMap<ActorKey, Actor>,
+        me: ActorKey,
+    ) -> Option<Question> {
+        if !self.alive {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let observation = Observation::new(id, grids, entities, me);
+
+        let mut line = serde_json::to_string(&observation).unwrap();
+        line.push('\n');
+        if let Err(e) = self
+            .stdin
+            .write_all(line.as_bytes())
+            .and_then(|_| self.stdin.flush())
+        {
+            eprintln!("bot '{}' can't be reached: {}", self.command, e);
+            self.alive = false;
+            return None;
+        }
+        Some(Question {
+            id,
+            legal_actions: observation.legal_actions,
+            deadline: Instant::now() + self.timeout,
+        })
+    }
+
+    /// checks for an answer without blocking, meant to be called once per frame
+    pub fn poll(&mut self, question: &Question) -> BotReply {
+        loop {
+            if let Some(reply) = self.answer(question, self.replies.try_recv()) {
+                return reply;
+            }
+        }
+    }
+
+    /// what a line from the bot means for `question`
+    /// None for an answer to an earlier question, the next line has to be read
+    fn answer(
+        &mut self,
+        question: &Question,
+        reply: Result<String, TryRecvError>,
+    ) -> Option<BotReply> {
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(TryRecvError::Empty) if Instant::now() < question.deadline => {
+                return Some(BotReply::Waiting)
+            }
+            Err(TryRecvError::Empty) => {
+                eprintln!("bot '{}' timed out, using built-in ai", self.command);
+                return Some(BotReply::Done(None));
+            }
+            Err(TryRecvError::Disconnected) => {
+                eprintln!("bot '{}' exited, using built-in ai", self.command);
+                self.alive = false;
+                return Some(BotReply::Done(None));
+            }
+        };
+        let reply: Reply = match serde_json::from_str(&reply) {
+            Ok(reply) => reply,
+            Err(e) => {
+                eprintln!("bot '{}' sent invalid reply: {}", self.command, e);
+                return Some(BotReply::Done(None));
+            }
+        };
+        // answer to an earlier observation that timed out
+        if reply.id.is_some_and(|reply_id| reply_id != question.id) {
+            return None;
+        }
+        if !question.legal_actions.contains(&reply.action) {
+            eprintln!(
+                "bot '{}' chose illegal action {:?}",
+                self.command, reply.action
+            );
+            return Some(BotReply::Done(None));
+        }
+        Some(BotReply::Done(Some(reply.action)))
+    }
+}
+
+impl Drop for ExternalBot {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    use crate::ascii_map::test_board;
+
+    fn small_board() -> (Grids, SlotMap<ActorKey, Actor>, ActorKey) {
+        let (grids, entities) = test_board(
+            "
+            .....
+            .....
+            ..r..
+            .....
+            ..b..
+
+            r: red_infantry
+            b: blue_infantry
+            ",
+        );
+        let me = actor_at_pos(&entities, ivec2(2, 2)).unwrap();
+        (grids, entities, me)
+    }
+
+    /// deletes the script once the test is done with it
+    struct TempScript(std::path::PathBuf);
+
+    impl Drop for TempScript {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// the script has to be kept alive as long as the bot
+    fn sh_bot(name: &str, script: &str, timeout_ms: u64) -> (ExternalBot, TempScript) {
+        // split_whitespace would tear the script apart, so go through a file
+        let path =
+            std::env::temp_dir().join(format!("comfy_wars_bot_{}_{}.sh", name, std::process::id()));
+        std::fs::write(&path, script).unwrap();
+        let command = format!("sh {}", path.display());
+        let bot = ExternalBot::spawn(&command, Duration::from_millis(timeout_ms)).unwrap();
+        (bot, TempScript(path))
+    }
+
+    #[test]
+    fn legal_reply_is_used() {
+        let (grids, entities, me) = small_board();
+        let script = r#"while read line; do echo '{"move_to": {"x": 2, "y": 3}, "attack": {"x": 2, "y": 4}}'; done"#;
+        let (mut bot, _script) = sh_bot("legal", script, 2000);
+        let action = bot.decide(&grids, &entities, me);
+        assert_eq!(
+            Some(UnitAction {
+                move_to: ivec2(2, 3),
+                attack: Some(ivec2(2, 4)),
+            }),
+            action
+        );
+    }
+
+    #[test]
+    fn illegal_reply_is_rejected() {
+        let (grids, entities, me) = small_board();
+        // too far away and no enemy at the attack position
+        let script = r#"while read line; do echo '{"move_to": {"x": 0, "y": 0}, "attack": {"x": 4, "y": 4}}'; done"#;
+        let (mut bot, _script) = sh_bot("illegal", script, 2000);
+        assert_eq!(None, bot.decide(&grids, &entities, me));
+    }
+
+    #[test]
+    fn timeout_falls_back_to_builtin_ai() {
+        let (grids, entities, me) = small_board();
+        let (bot, _script) = sh_bot("silent", "while read line; do :; done", 50);
+        let mut controller = ai::Controller {
+            bot: Some(bot),
+            ..Default::default()
+        };
+        let action = controller.decide(&grids, &entities, me);
+        assert_eq!(
+            ai::decide(&grids, &entities, me, &ai::AiConfig::default()),
+            action
+        );
+    }
+
+    #[test]
+    fn polling_does_not_block() {
+        let (grids, entities, me) = small_board();
+        // answers only after a while
+        let script = r#"while read line; do sleep 0.2; echo '{"move_to": {"x": 2, "y": 2}, "attack": null}'; done"#;
+        let (mut bot, _script) = sh_bot("slow", script, 2000);
+        let question = bot.ask(&grids, &entities, me).unwrap();
+        let started = Instant::now();
+        assert!(matches!(bot.poll(&question), BotReply::Waiting));
+        assert!(started.elapsed() < Duration::from_millis(100));
+        let action = loop {
+            match bot.poll(&question) {
+                BotReply::Waiting => std::thread::sleep(Duration::from_millis(10)),
+                BotReply::Done(action) => break action,
+            }
+        };
+        assert_eq!(Some(ivec2(2, 2)), action.map(|a| a.move_to));
+    }
+}
@@ -28,8 +28,9 @@ impl CameraWrapper {
         let offset = Vec2f { x: -160., y: -40. };
         let offset_tween = Tweener::linear(offset, offset, 0.00);
 
-        let camera = Self::create_camera(scale, offset.into());
-        set_camera(&camera);
+        // the real camera needs the screen size, `process` creates it every frame
+        // until then this one works without a window, e.g. in tests
+        let camera = Camera2D::default();
         CameraWrapper {
             scale,
             scale_exp,
@@ -103,6 +104,26 @@ impl CameraWrapper {
         self.offset.y += y;
     }
 
+    /// pans just enough to get the world position `pos` on screen,
+    /// keeping `margin` world units between it and the screen edges
+    pub fn keep_in_view(&mut self, pos: impl Into<Vec2>, margin: f32) {
+        let pos = pos.into();
+        let view = self.visible_rect();
+        let (min, max) = (view.point() + margin, view.point() + view.size() - margin);
+        let shift = (pos - min).min(Vec2::ZERO) + (pos - max).max(Vec2::ZERO);
+        self.move_camera((shift.x, shift.y));
+    }
+
+    /// the part of the world that is on screen
+    pub fn visible_rect(&self) -> Rect {
+        let a: Vec2 = self.screen_to_world(vec2(0., 0.)).into();
+        let b: Vec2 = self
+            .screen_to_world(vec2(screen_width(), screen_height()))
+            .into();
+        let (min, max) = (a.min(b), a.max(b));
+        Rect::new(min.x, min.y, max.x - min.x, max.y - min.y)
+    }
+
     pub fn screen_to_world(&self, pos: impl Into<Vec2>) -> Vec2f {
         let pos = pos.into();
         self.camera.screen_to_world(pos).into()
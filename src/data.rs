@@ -1,4 +1,5 @@
 /// stuff loaded from ldtk and co
+use crate::util::IVec2Proxy;
 use crate::*;
 use grids::Grid;
 use nanoserde::*;
@@ -22,6 +23,7 @@ pub struct EntityOnMap {
     pub pos: [i32; 2],
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(DeJson, Debug)]
 pub struct LDTK {
     pub levels: Vec<Level>,
@@ -45,22 +47,270 @@ pub struct Layer {
     int_grid: Vec<i32>,
     #[nserde(rename = "autoLayerTiles")]
     pub auto_tiles: Vec<AutoTile>,
+    #[nserde(rename = "entityInstances")]
+    pub entities: Vec<EntityInstance>,
     #[nserde(rename = "__cWid")]
     pub width: i32,
     #[nserde(rename = "__cHei")]
     pub height: i32,
 }
 
+#[derive(DeJson, Debug)]
+pub struct EntityInstance {
+    #[nserde(rename = "__identifier")]
+    pub def: String,
+    #[nserde(rename = "__grid")]
+    pub pos: [i32; 2],
+}
+
 #[derive(DeJson, Debug)]
 pub struct AutoTile {
     pub px: [f32; 2],
     pub src: [i32; 2],
 }
 
-pub fn grid_from_layer<T: Clone, F: Fn(i32) -> T>(layer: &Layer, converter: F) -> Grid<T> {
+/// all grids in here have the same dimensions
+/// bigger x is right
+/// bigger y is down (reverse of what comfy uses atm)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grids {
+    pub ground: Grid<GroundType>,
+    pub terrain: Grid<TerrainType>,
+    #[serde(default)]
+    pub topology: Topology,
+}
+
+impl Default for Grids {
+    fn default() -> Self {
+        Self {
+            ground: Grid::new(0, 0, Default::default()),
+            terrain: Grid::new(0, 0, Default::default()),
+            topology: Default::default(),
+        }
+    }
+}
+
+pub fn grid_from_layer<T: Clone, F: Fn(i32) -> Result<T>>(
+    layer: &Layer,
+    converter: F,
+) -> Result<Grid<T>> {
     let width = layer.width;
     let height = layer.height;
-    Grid::filled_with(width, height, |x, y| {
-        converter(layer.int_grid[(x + y * width) as usize])
-    })
+    if layer.int_grid.len() != (width * height).max(0) as usize {
+        anyhow::bail!(
+            "layer {} has {} values, not {}x{}",
+            layer.id,
+            layer.int_grid.len(),
+            width,
+            height
+        );
+    }
+    let values = layer
+        .int_grid
+        .iter()
+        .map(|i| converter(*i))
+        .collect::<Result<Vec<T>>>()?;
+    Ok(Grid::filled_with(width, height, |x, y| {
+        values[(x + y * width) as usize].clone()
+    }))
+}
+
+/// builds the gameplay grids from the intgrid layers of a level
+pub fn grids_from_level(level: &Level) -> Result<Grids> {
+    let mut grids = Grids {
+        ground: Grid::new(
+            level.pixel_width / GRIDSIZE,
+            level.pixel_height / GRIDSIZE,
+            Default::default(),
+        ),
+        terrain: Grid::new(
+            level.pixel_width / GRIDSIZE,
+            level.pixel_height / GRIDSIZE,
+            Default::default(),
+        ),
+        topology: Topology::default(),
+    };
+    for layer in level.layers.iter() {
+        match layer.id.as_str() {
+            "groundgrid" => {
+                grids.ground = grid_from_layer(layer, |i| match i {
+                    1 => Ok(GroundType::Ground),
+                    2 => Ok(GroundType::Water),
+                    _ => anyhow::bail!("unsupported ground type {}", i),
+                })?;
+            }
+            "infrastructuregrid" => {
+                grids.terrain = grid_from_layer(layer, |i| match i {
+                    0 => Ok(TerrainType::None),
+                    1..=4 => Ok(TerrainType::Street),
+                    5 => Ok(TerrainType::Forest),
+                    _ => anyhow::bail!("unsupported terrain type {}", i),
+                })?;
+            }
+            _ => {}
+        }
+    }
+    if grids.ground.size() != grids.terrain.size() {
+        anyhow::bail!(
+            "ground is {} tiles, terrain {}",
+            grids.ground.size(),
+            grids.terrain.size()
+        );
+    }
+    Ok(grids)
+}
+
+/// spawns the units placed in the entity layers of a level
+pub fn actors_from_level(
+    level: &Level,
+    entity_defs: &HashMap<String, EntityDef>,
+) -> Result<Vec<Actor>> {
+    level
+        .layers
+        .iter()
+        .flat_map(|layer| layer.entities.iter())
+        .map(|e| {
+            let def = entity_defs
+                .get(&e.def)
+                .ok_or_else(|| anyhow::anyhow!("unknown unit {}", e.def))?;
+            Ok(Actor::from_def(&e.def, def, e.pos.into()))
+        })
+        .collect()
+}
+
+/// everything needed to start a match on a map
+#[derive(Debug, Clone)]
+pub struct GameMap {
+    pub grids: Grids,
+    pub actors: Vec<Actor>,
+}
+
+impl GameMap {
+    pub fn from_level(level: &Level, entity_defs: &HashMap<String, EntityDef>) -> Result<Self> {
+        Ok(Self {
+            grids: grids_from_level(level)?,
+            actors: actors_from_level(level, entity_defs)?,
+        })
+    }
+
+    /// loads a level of an ldtk file from disk
+    pub fn load_ldtk(path: &str, level: usize) -> Result<Self> {
+        let ldtk: LDTK = DeJson::deserialize_json(&std::fs::read_to_string(path)?)?;
+        let level = ldtk
+            .levels
+            .get(level)
+            .ok_or_else(|| anyhow::anyhow!("map has no level {}", level))?;
+        Self::from_level(level, &entity_defs()?)
+    }
+
+    /// loads a map saved by the editor, the first level of an ldtk file
+    /// or a text map if the file ends with .txt
+    pub fn load(path: &str) -> Result<Self> {
+        if path.ends_with(".ldtk") {
+            return Self::load_ldtk(path, 0);
+        }
+        let text = std::fs::read_to_string(path)?;
+        if path.ends_with(".txt") {
+            return Self::from_ascii(&text, &entity_defs()?);
+        }
+        Self::from_json(&text, &entity_defs()?)
+    }
+
+    /// saves as a text map if the file ends with .txt, as json otherwise
+    pub fn save(&self, path: &str) -> Result<()> {
+        let text = if path.ends_with(".txt") {
+            self.to_ascii()?
+        } else {
+            self.to_json()
+        };
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        let file = MapFile {
+            grids: self.grids.clone(),
+            units: self
+                .actors
+                .iter()
+                .map(|actor| UnitPlacement {
+                    def: actor.sprite_name.clone(),
+                    pos: actor.pos,
+                })
+                .collect(),
+        };
+        serde_json::to_string_pretty(&file).unwrap()
+    }
+
+    pub fn from_json(json: &str, entity_defs: &HashMap<String, EntityDef>) -> Result<Self> {
+        let file: MapFile = serde_json::from_str(json)?;
+        let mut actors = Vec::new();
+        for unit in file.units {
+            let def = entity_defs
+                .get(&unit.def)
+                .ok_or_else(|| anyhow::anyhow!("unknown unit {}", unit.def))?;
+            actors.push(Actor::from_def(&unit.def, def, unit.pos));
+        }
+        Ok(Self {
+            grids: file.grids,
+            actors,
+        })
+    }
+}
+
+/// the unit types that can be placed on maps, by name
+pub fn entity_defs() -> Result<HashMap<String, EntityDef>> {
+    Ok(DeJson::deserialize_json(kf_include_str!(
+        "/assets/entities_def.json"
+    ))?)
+}
+
+/// map file written by the editor
+/// only the starting state of a map, units always start at full hp
+#[derive(Serialize, Deserialize)]
+struct MapFile {
+    grids: Grids,
+    units: Vec<UnitPlacement>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UnitPlacement {
+    /// name in entities_def.json
+    def: String,
+    #[serde(with = "IVec2Proxy")]
+    pos: IVec2,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(int_grid: &str, entity: &str) -> Level {
+        let json = format!(
+            r#"{{"pxWid": 32, "pxHei": 16, "layerInstances": [{{
+                "__identifier": "groundgrid", "intGridCsv": [{}],
+                "autoLayerTiles": [], "__cWid": 2, "__cHei": 1,
+                "entityInstances": [{{"__identifier": "{}", "__grid": [0, 0]}}]
+            }}]}}"#,
+            int_grid, entity
+        );
+        DeJson::deserialize_json(&json).unwrap()
+    }
+
+    #[test]
+    fn bad_levels_are_errors() {
+        let defs = entity_defs().unwrap();
+        let map = GameMap::from_level(&level("1, 2", "red_tank"), &defs).unwrap();
+        assert_eq!(GroundType::Water, map.grids.ground[(1, 0)]);
+        assert_eq!(1, map.actors.len());
+
+        let error = |int_grid, entity| {
+            GameMap::from_level(&level(int_grid, entity), &defs)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("1, 7", "red_tank").contains("ground type 7"));
+        assert!(error("1", "red_tank").contains("1 values"));
+        assert!(error("1, 1", "dragon").contains("dragon"));
+    }
 }
@@ -12,8 +12,8 @@ pub fn cw_debug(s: impl Into<String>) {
 
 pub fn cw_draw_debug_window() {
     let mut lines = DEBUG_LINES.borrow_mut();
-    if lines.len() > 0 {
-        egui::Window::new("Adhoc Debug Window").show(&egui(), |ui| {
+    if !lines.is_empty() {
+        egui::Window::new("Adhoc Debug Window").show(egui(), |ui| {
             for line in lines.drain(..) {
                 ui.label(line);
             }
@@ -1,87 +1,218 @@
-use itertools::Itertools;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 
 use crate::comfy_compat::{ivec2, IVec2};
 use crate::grids::Grid;
+use crate::topology::Topology;
 
-pub fn get_neighbors<T>(pos: IVec2, grid: &Grid<T>) -> Vec<IVec2> {
-    let x = pos.x;
-    let y = pos.y;
-    [(x - 1, y), (x + 1, y), (x, y + 1), (x, y - 1)]
-        .into_iter()
-        .filter(|(x, y)| 0 <= *x && *x < grid.width && 0 <= *y && *y < grid.height)
-        .map(|(x, y)| ivec2(x, y))
-        .collect_vec()
+/// cost of tiles that were not reached
+pub const UNREACHABLE: i32 = i32::MAX;
+
+/// result of a dijkstra search from one or more seeds
+#[derive(Debug, Clone)]
+pub struct DijkstraMap {
+    /// cheapest cost from any seed, `UNREACHABLE` if not reached
+    pub cost: Grid<i32>,
+    /// previous tile on the cheapest path, None for seeds and unreached tiles
+    pub predecessor: Grid<Option<IVec2>>,
 }
 
-pub fn dijkstra<F: Fn(IVec2) -> i32>(grid: &mut Grid<i32>, seed: &[IVec2], cost: F) {
-    let mut next: Vec<IVec2> = seed
-        .iter()
-        .flat_map(|pos| get_neighbors(*pos, grid))
-        .collect_vec();
-    next.extend(seed.iter()); // sometimes its necessary to recompute seeds too
-
-    while !next.is_empty() {
-        let buffer = next.drain(..).collect_vec();
-        for pos in buffer.into_iter() {
-            let neighbor_max = {
-                get_neighbors(pos, grid)
-                    .into_iter()
-                    .map(|pos| grid.get_clamped(pos.x, pos.y))
-                    .max()
-                    .cloned()
-            };
-            if let Some(neighbor_max) = neighbor_max {
-                let v = *grid.get_clamped_v(pos);
-                let c = cost(pos);
-                if neighbor_max > v + c {
-                    let new_val = neighbor_max - c;
-                    *grid.get_mut(pos.x, pos.y) = new_val;
-                    next.extend(
-                        get_neighbors(pos, grid)
-                            .into_iter()
-                            .filter(|pos| *grid.get(pos.x, pos.y) < new_val - cost(*pos)),
-                    );
-                }
+impl DijkstraMap {
+    pub fn is_reached(&self, pos: IVec2) -> bool {
+        self.cost.is_valid(pos) && self.cost[pos] != UNREACHABLE
+    }
+
+    /// cheapest path from a seed to `goal`, both inclusive
+    pub fn path_to(&self, goal: IVec2) -> Option<Vec<IVec2>> {
+        if !self.is_reached(goal) {
+            return None;
+        }
+        let mut path = vec![goal];
+        let mut pos = goal;
+        while let Some(prev) = self.predecessor[pos] {
+            path.push(prev);
+            pos = prev;
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// costs with unreached tiles set to -1, for display
+    pub fn reached_costs(&self) -> Grid<i32> {
+        let mut grid = self.cost.clone();
+        for v in grid.iter_values_mut() {
+            if *v == UNREACHABLE {
+                *v = -1;
+            }
+        }
+        grid
+    }
+}
+
+/// dijkstra using a binary heap
+/// `cost(pos)` is the cost of entering `pos`, `topology` decides what the neighbors are
+/// tiles which would cost more than `max_cost` are not expanded
+///
+/// ties are broken by position (smaller y first, then smaller x)
+/// so the result does not depend on iteration order
+pub fn dijkstra_map<F: Fn(IVec2) -> i32>(
+    width: i32,
+    height: i32,
+    topology: Topology,
+    seeds: &[IVec2],
+    max_cost: Option<i32>,
+    cost: F,
+) -> DijkstraMap {
+    let mut result = DijkstraMap {
+        cost: Grid::new(width, height, UNREACHABLE),
+        predecessor: Grid::new(width, height, None),
+    };
+    let max_cost = max_cost.unwrap_or(UNREACHABLE - 1);
+    let mut heap = BinaryHeap::new();
+    for seed in seeds.iter() {
+        if !result.cost.is_valid(*seed) {
+            continue;
+        }
+        result.cost[*seed] = 0;
+        heap.push(heap_entry(0, *seed, width));
+    }
+
+    while let Some(Reverse(packed)) = heap.pop() {
+        let (c, pos) = unpack_heap_entry(packed, width);
+        if c > result.cost[pos] {
+            // outdated entry
+            continue;
+        }
+        for (dx, dy) in topology.offsets(pos) {
+            let npos = ivec2(pos.x + dx, pos.y + dy);
+            if !result.cost.is_valid(npos) {
+                continue;
+            }
+            let nc = c.saturating_add(topology.step_cost(pos, npos, cost(npos)));
+            if nc <= max_cost && nc < result.cost[npos] {
+                result.cost[npos] = nc;
+                result.predecessor[npos] = Some(pos);
+                heap.push(heap_entry(nc, npos, width));
             }
         }
     }
+    result
+}
+
+/// heap entries are packed into a single integer: the priority in the upper bits,
+/// the tile index (y * width + x) in the lower ones, which gives the tie-breaking for free
+fn heap_entry(priority: i32, pos: IVec2, width: i32) -> Reverse<u64> {
+    Reverse(((priority as u64) << 32) | (pos.y * width + pos.x) as u64)
 }
 
-/// returns path that follows increasing values until it reaches a local maximium
-pub fn dijkstra_path(grid: &Grid<i32>, start: IVec2) -> Vec<IVec2> {
-    let mut path = Vec::new();
-    if start.x < 0 || start.y <= 0 || start.x >= grid.width || start.y >= grid.height {
-        return path;
-    }
-    let mut pos = start;
-    let mut v = grid[pos];
-    if v <= 0 {
-        return path;
-    }
-    path.push(start);
-    // do while at home
-    while {
-        // neighbor with maximum value
-        let (npos, nv) = get_neighbors(pos, grid)
-            .into_iter()
-            .map(|npos| (npos, grid[npos]))
-            .max_by_key(|(_, v)| *v)
-            .unwrap();
-        if nv > v {
-            path.push(npos);
-            pos = npos;
-            v = nv;
-            true
-        } else {
-            false
-        }
-    } {}
-    path
+fn unpack_heap_entry(packed: u64, width: i32) -> (i32, IVec2) {
+    let priority = (packed >> 32) as i32;
+    let index = (packed & u32::MAX as u64) as i32;
+    (priority, ivec2(index % width, index / width))
+}
+
+/// A* search for the cheapest path from `start` to `goal`, both inclusive
+/// `cost(pos)` is the cost of entering `pos` and has to be at least 1,
+/// otherwise the `Topology::min_cost` heuristic overestimates
+/// returns None if the goal can't be reached within `max_cost`
+pub fn astar<F: Fn(IVec2) -> i32>(
+    width: i32,
+    height: i32,
+    topology: Topology,
+    start: IVec2,
+    goal: IVec2,
+    max_cost: Option<i32>,
+    cost: F,
+) -> Option<Vec<IVec2>> {
+    let mut costs = Grid::new(width, height, UNREACHABLE);
+    if !costs.is_valid(start) || !costs.is_valid(goal) {
+        return None;
+    }
+    let mut predecessor: Grid<Option<IVec2>> = Grid::new(width, height, None);
+    let max_cost = max_cost.unwrap_or(UNREACHABLE - 1);
+    let heuristic = |pos: IVec2| topology.min_cost(pos, goal);
+
+    let mut heap = BinaryHeap::new();
+    costs[start] = 0;
+    heap.push(heap_entry(heuristic(start), start, width));
+
+    while let Some(Reverse(packed)) = heap.pop() {
+        let (estimate, pos) = unpack_heap_entry(packed, width);
+        let c = costs[pos];
+        if estimate > c.saturating_add(heuristic(pos)) {
+            // outdated entry
+            continue;
+        }
+        if pos == goal {
+            let mut path = vec![goal];
+            let mut pos = goal;
+            while let Some(prev) = predecessor[pos] {
+                path.push(prev);
+                pos = prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for (dx, dy) in topology.offsets(pos) {
+            let npos = ivec2(pos.x + dx, pos.y + dy);
+            if !costs.is_valid(npos) {
+                continue;
+            }
+            let nc = c.saturating_add(topology.step_cost(pos, npos, cost(npos)));
+            let estimate = nc.saturating_add(heuristic(npos));
+            if estimate <= max_cost && nc < costs[npos] {
+                costs[npos] = nc;
+                predecessor[npos] = Some(pos);
+                heap.push(heap_entry(estimate, npos, width));
+            }
+        }
+    }
+    None
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use itertools::Itertools;
+
+    /// the old value propagation, kept to check and benchmark `dijkstra_map` against
+    fn get_neighbors<T>(pos: IVec2, grid: &Grid<T>) -> Vec<IVec2> {
+        grid.neighbors(pos, Topology::Orthogonal)
+    }
+
+    fn dijkstra<F: Fn(IVec2) -> i32>(grid: &mut Grid<i32>, seed: &[IVec2], cost: F) {
+        let mut next: Vec<IVec2> = seed
+            .iter()
+            .flat_map(|pos| get_neighbors(*pos, grid))
+            .collect_vec();
+        next.extend(seed.iter()); // sometimes its necessary to recompute seeds too
+
+        while !next.is_empty() {
+            let buffer = next.drain(..).collect_vec();
+            for pos in buffer.into_iter() {
+                let neighbor_max = {
+                    get_neighbors(pos, grid)
+                        .into_iter()
+                        .map(|pos| grid.get_clamped(pos.x, pos.y))
+                        .max()
+                        .cloned()
+                };
+                if let Some(neighbor_max) = neighbor_max {
+                    let v = *grid.get_clamped_v(pos);
+                    let c = cost(pos);
+                    if neighbor_max > v + c {
+                        let new_val = neighbor_max - c;
+                        *grid.get_mut(pos.x, pos.y) = new_val;
+                        next.extend(
+                            get_neighbors(pos, grid)
+                                .into_iter()
+                                .filter(|pos| *grid.get(pos.x, pos.y) < new_val - cost(*pos)),
+                        );
+                    }
+                }
+            }
+        }
+    }
 
     #[test]
     fn get_neighbors_test() {
@@ -125,4 +256,193 @@ mod tests {
         dijkstra(&mut grid, &[pos, pos2], |_| 1);
         assert_eq!(3, *grid.get(2, 5));
     }
+
+    #[test]
+    fn dijkstra_map_costs_and_paths() {
+        // a wall with a single gap at the bottom
+        let wall = |pos: IVec2| pos.x == 2 && pos.y < 4;
+        let cost = |pos: IVec2| if wall(pos) { 100 } else { 1 };
+        let map = dijkstra_map(5, 5, Topology::Orthogonal, &[ivec2(0, 0)], None, cost);
+        assert_eq!(0, map.cost[(0, 0)]);
+        assert_eq!(4, map.cost[(0, 4)]);
+        assert_eq!(12, map.cost[(4, 0)]);
+
+        let path = map.path_to(ivec2(4, 0)).unwrap();
+        assert_eq!(13, path.len());
+        assert_eq!(ivec2(0, 0), path[0]);
+        assert_eq!(ivec2(4, 0), path[12]);
+        assert!(path.contains(&ivec2(2, 4)));
+        for (a, b) in path.iter().tuple_windows() {
+            assert_eq!(1, (*a - *b).abs().element_sum());
+        }
+
+        // budget
+        let map = dijkstra_map(5, 5, Topology::Orthogonal, &[ivec2(0, 0)], Some(3), cost);
+        assert!(!map.is_reached(ivec2(3, 0)));
+        assert!(map.is_reached(ivec2(0, 3)));
+        assert!(map.path_to(ivec2(4, 4)).is_none());
+    }
+
+    #[test]
+    fn dijkstra_map_tie_breaking() {
+        // many equally cheap paths, the result must not change between runs
+        // and prefers going up (smaller y) first
+        let map = dijkstra_map(4, 4, Topology::Orthogonal, &[ivec2(0, 0)], None, |_| 1);
+        let path = map.path_to(ivec2(3, 3)).unwrap();
+        let again = dijkstra_map(4, 4, Topology::Orthogonal, &[ivec2(0, 0)], None, |_| 1);
+        assert_eq!(path, again.path_to(ivec2(3, 3)).unwrap());
+        assert_eq!(7, path.len());
+    }
+
+    #[test]
+    fn dijkstra_map_multiple_seeds() {
+        let map = dijkstra_map(
+            10,
+            1,
+            Topology::Orthogonal,
+            &[ivec2(0, 0), ivec2(9, 0)],
+            None,
+            |_| 1,
+        );
+        assert_eq!(4, map.cost[(4, 0)]);
+        assert_eq!(4, map.cost[(5, 0)]);
+        assert_eq!(
+            Some(vec![ivec2(9, 0), ivec2(8, 0)]),
+            map.path_to(ivec2(8, 0))
+        );
+    }
+
+    #[test]
+    fn astar_matches_dijkstra() {
+        let cost = |pos: IVec2| {
+            if pos.x == 4 && pos.y != 7 {
+                9999
+            } else {
+                1 + (pos.x * 7 + pos.y * 13) % 3
+            }
+        };
+        let start = ivec2(1, 2);
+        for topology in Topology::ALL {
+            let map = dijkstra_map(9, 9, topology, &[start], None, cost);
+            for goal in [ivec2(8, 0), ivec2(1, 2), ivec2(3, 8), ivec2(6, 6)] {
+                let path = astar(9, 9, topology, start, goal, None, cost).unwrap();
+                assert_eq!(start, path[0]);
+                assert_eq!(goal, *path.last().unwrap());
+                let path_cost: i32 = path
+                    .iter()
+                    .tuple_windows()
+                    .map(|(a, b)| topology.step_cost(*a, *b, cost(*b)))
+                    .sum();
+                assert_eq!(map.cost[goal], path_cost, "{:?}", topology);
+            }
+        }
+    }
+
+    #[test]
+    fn diagonal_shortcut() {
+        let octile = dijkstra_map(5, 5, Topology::Octile, &[ivec2(0, 0)], None, |_| 2);
+        // 4 diagonal steps at 3 instead of 8 straight steps at 2
+        assert_eq!(12, octile.cost[(4, 4)]);
+        assert_eq!(5, octile.path_to(ivec2(4, 4)).unwrap().len());
+
+        let hex = dijkstra_map(5, 5, Topology::Hex, &[ivec2(0, 0)], None, |_| 1);
+        assert_eq!(
+            Topology::Hex.distance(ivec2(0, 0), ivec2(4, 4)),
+            hex.cost[(4, 4)]
+        );
+    }
+
+    #[test]
+    fn astar_budget() {
+        let path = astar(
+            10,
+            1,
+            Topology::Orthogonal,
+            ivec2(0, 0),
+            ivec2(5, 0),
+            Some(5),
+            |_| 1,
+        )
+        .unwrap();
+        assert_eq!(6, path.len());
+        assert_eq!(
+            None,
+            astar(
+                10,
+                1,
+                Topology::Orthogonal,
+                ivec2(0, 0),
+                ivec2(6, 0),
+                Some(5),
+                |_| 1
+            )
+        );
+        assert_eq!(
+            None,
+            astar(
+                10,
+                1,
+                Topology::Orthogonal,
+                ivec2(0, 0),
+                ivec2(10, 0),
+                None,
+                |_| 1
+            )
+        );
+    }
+
+    /// run with `cargo test --release dijkstra_bench -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn dijkstra_bench() {
+        use std::time::Instant;
+        let size = 256;
+        // mixed terrain with walls that force detours
+        let cost = |pos: IVec2| {
+            if pos.x % 16 == 8 && pos.y % 64 != 0 {
+                9999
+            } else {
+                1 + (pos.x * 7 + pos.y * 13) % 3
+            }
+        };
+        let seed = ivec2(size / 2, size / 2);
+        let runs = 10;
+
+        let start = Instant::now();
+        let mut grid = Grid::new(size, size, 0);
+        for _ in 0..runs {
+            grid = Grid::new(size, size, 0);
+            grid[seed] = 100_000;
+            dijkstra(&mut grid, &[seed], cost);
+        }
+        let old = start.elapsed() / runs;
+
+        let start = Instant::now();
+        let mut map = dijkstra_map(1, 1, Topology::Orthogonal, &[], None, cost);
+        for _ in 0..runs {
+            map = dijkstra_map(size, size, Topology::Orthogonal, &[seed], None, cost);
+        }
+        let new = start.elapsed() / runs;
+
+        // same distances
+        for (pos, v) in grid.iter_coords() {
+            assert_eq!(100_000 - v, map.cost[pos]);
+        }
+        println!(
+            "{0}x{0} map: value propagation {1:?}, binary heap {2:?}",
+            size, old, new
+        );
+
+        // single target query like the move preview
+        let goal = seed + ivec2(5, -3);
+        let start = Instant::now();
+        for _ in 0..runs {
+            astar(size, size, Topology::Orthogonal, seed, goal, Some(8), cost);
+        }
+        println!(
+            "{0}x{0} map: astar to nearby tile {1:?}",
+            size,
+            start.elapsed() / runs
+        );
+    }
 }
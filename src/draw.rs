@@ -0,0 +1,413 @@
+/// draw commands as plain data
+/// the game queues them during a frame, a backend executes them sorted by z:
+/// the macroquad backend draws them, the recorder keeps them for tests
+use crate::*;
+use std::sync::Arc;
+
+/// quads per `geometry` call, below what macroquad takes in a single draw call
+const BATCH_QUADS: usize = 800;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DrawKind {
+    /// part of the tilemap, `name` as in sprites.json
+    Sprite {
+        name: Option<String>,
+        src: Rect,
+    },
+    Rectangle {
+        w: f32,
+        h: f32,
+    },
+    Line {
+        to: Vec2,
+        thickness: f32,
+    },
+    Text {
+        text: String,
+        font_size: u16,
+        font_scale: f32,
+    },
+    /// sprites turned into vertices on the cpu ahead of time, e.g. the static tile layers
+    Batch(Arc<SpriteBatch>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DrawCommand {
+    pub z_level: i32,
+    /// in world pixels, after `GameState::display_pos`
+    pub pos: Vec2,
+    pub color: Color,
+    pub kind: DrawKind,
+}
+
+pub trait DrawBackend {
+    fn execute(&mut self, command: &DrawCommand);
+    /// called after the last command of a frame
+    fn finish(&mut self) {}
+}
+
+/// quads of one texture in cpu memory, drawn with as few `geometry` calls as possible
+/// macroquad copies the vertices to the gpu again on every `draw`
+#[derive(Debug, Clone, Default)]
+pub struct SpriteBatch {
+    vertices: Vec<Vertex>,
+    /// relative to the chunk of `BATCH_QUADS` a quad is in
+    indices: Vec<u16>,
+}
+
+impl SpriteBatch {
+    /// `src` in pixels of a texture of `texture_size`
+    pub fn push(&mut self, pos: Vec2, src: Rect, color: Color, texture_size: Vec2) {
+        let first = (self.len() % BATCH_QUADS * 4) as u16;
+        self.vertices
+            .extend(quad_vertices(pos, src, color, texture_size));
+        self.indices.extend([0, 1, 2, 0, 2, 3].map(|i| first + i));
+    }
+
+    /// number of quads
+    pub fn len(&self) -> usize {
+        self.vertices.len() / 4
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// keeps the memory for the next frame
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+
+    /// how many `geometry` calls `draw` makes
+    pub fn chunks(&self) -> usize {
+        self.len().div_ceil(BATCH_QUADS)
+    }
+
+    pub fn draw(&self, texture: &Texture2D) {
+        if self.is_empty() {
+            return;
+        }
+        let context = unsafe { get_internal_gl() };
+        context.quad_gl.texture(Some(texture));
+        context.quad_gl.draw_mode(DrawMode::Triangles);
+        let chunks = self.vertices.chunks(BATCH_QUADS * 4);
+        for (vertices, indices) in chunks.zip(self.indices.chunks(BATCH_QUADS * 6)) {
+            context.quad_gl.geometry(vertices, indices);
+        }
+    }
+}
+
+/// vertex doesn't implement PartialEq
+impl PartialEq for SpriteBatch {
+    fn eq(&self, other: &Self) -> bool {
+        self.indices == other.indices
+            && self.vertices.len() == other.vertices.len()
+            && self
+                .vertices
+                .iter()
+                .zip(&other.vertices)
+                .all(|(a, b)| a.position == b.position && a.uv == b.uv && a.color == b.color)
+    }
+}
+
+/// the corners of a sprite at `pos`, clockwise from the top left
+fn quad_vertices(pos: Vec2, src: Rect, color: Color, texture_size: Vec2) -> [Vertex; 4] {
+    let [width, height] = texture_size.to_array();
+    let Rect { x: sx, y: sy, w, h } = src;
+    let Vec2 { x, y } = pos;
+    // offsetting the uv slightly inward solves the problem of seams
+    // it only happened at some dpi in the web build
+    const OFFSET: f32 = 0.001;
+    #[rustfmt::skip]
+    let vertices = [
+        Vertex::new(x,     y,     0.,  sx     /width +OFFSET,  sy     /height +OFFSET, color),
+        Vertex::new(x + w, y,     0., (sx + w)/width -OFFSET,  sy     /height +OFFSET, color),
+        Vertex::new(x + w, y + h, 0., (sx + w)/width -OFFSET, (sy + h)/height -OFFSET, color),
+        Vertex::new(x,     y + h, 0.,  sx     /width +OFFSET, (sy + h)/height -OFFSET, color),
+    ];
+    vertices
+}
+
+/// draws to the screen, every sprite comes from the tilemap
+/// consecutive sprites, which are sorted by z, go into one batch
+/// until something else is drawn in between
+pub struct MacroquadBackend<'a> {
+    tilemap: &'a Texture2D,
+    pending: SpriteBatch,
+}
+
+impl<'a> MacroquadBackend<'a> {
+    pub fn new(tilemap: &'a Texture2D) -> Self {
+        Self {
+            tilemap,
+            pending: Default::default(),
+        }
+    }
+
+    fn draw_pending(&mut self) {
+        self.pending.draw(self.tilemap);
+        self.pending.clear();
+    }
+}
+
+impl DrawBackend for MacroquadBackend<'_> {
+    fn execute(&mut self, command: &DrawCommand) {
+        let (pos, color) = (command.pos, command.color);
+        match &command.kind {
+            DrawKind::Sprite { src, .. } => {
+                self.pending.push(pos, *src, color, self.tilemap.size());
+            }
+            DrawKind::Batch(batch) => {
+                self.draw_pending();
+                batch.draw(self.tilemap);
+            }
+            DrawKind::Rectangle { w, h } => {
+                self.draw_pending();
+                draw_rectangle(pos.x, pos.y, *w, *h, color);
+            }
+            DrawKind::Line { to, thickness } => {
+                self.draw_pending();
+                draw_line(pos.x, pos.y, to.x, to.y, *thickness, color);
+            }
+            DrawKind::Text {
+                text,
+                font_size,
+                font_scale,
+            } => {
+                self.draw_pending();
+                let params = TextParams {
+                    font_size: *font_size,
+                    font_scale: *font_scale,
+                    color,
+                    ..Default::default()
+                };
+                draw_text_ex(text, pos.x, pos.y, params);
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        self.draw_pending();
+    }
+}
+
+/// keeps what would have been drawn, in drawing order
+#[derive(Debug, Default)]
+pub struct Recorder {
+    pub commands: Vec<DrawCommand>,
+}
+
+impl DrawBackend for Recorder {
+    fn execute(&mut self, command: &DrawCommand) {
+        self.commands.push(command.clone());
+    }
+}
+
+impl Recorder {
+    /// every drawn sprite with this name
+    pub fn sprites(&self, name: &str) -> Vec<&DrawCommand> {
+        self.commands
+            .iter()
+            .filter(|c| matches!(&c.kind, DrawKind::Sprite { name: Some(n), .. } if n == name))
+            .collect()
+    }
+
+    /// names of the drawn sprites, in drawing order
+    pub fn sprite_names(&self) -> Vec<&str> {
+        self.commands
+            .iter()
+            .filter_map(|c| match &c.kind {
+                DrawKind::Sprite { name: Some(n), .. } => Some(n.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game_state() -> GameState {
+        let mut s = GameState::new(null_object_queue_handle());
+        s.entity_defs = entity_defs().unwrap();
+        s.sprites = load_sprites(&s.entity_defs);
+        s.grids = Grids {
+            ground: Grid::new(4, 4, GroundType::Ground),
+            terrain: Grid::new(4, 4, TerrainType::None),
+            topology: Topology::default(),
+        };
+        s
+    }
+
+    #[test]
+    fn damaged_units_show_their_hp() {
+        let mut s = game_state();
+        let def = &s.entity_defs["red_tank"];
+        let mut damaged = Actor::from_def("red_tank", def, ivec2(1, 2));
+        damaged.hp = 7;
+        let healthy = Actor::from_def("red_tank", def, ivec2(3, 0));
+        s.entities.insert(damaged);
+        s.entities.insert(healthy);
+        draw_actors(&mut s);
+
+        let mut recorder = Recorder::default();
+        s.flush_draw_buffer(&mut recorder);
+        assert_eq!(2, recorder.sprites("red_tank").len());
+        let hp = recorder.sprites("hp_7");
+        assert_eq!(1, hp.len());
+        assert_eq!(Z_UNIT_HP, hp[0].z_level);
+        assert_eq!(vec2(16., 32.), hp[0].pos);
+        // drawn on top of the unit
+        let names = recorder.sprite_names();
+        let position = |name| names.iter().position(|n| *n == name).unwrap();
+        assert!(position("hp_7") > position("red_tank"));
+        assert!(s.draw_buffer.borrow().is_empty());
+    }
+
+    #[test]
+    fn arrows_follow_the_path() {
+        let s = game_state();
+        let path = [ivec2(0, 0), ivec2(1, 0), ivec2(2, 0), ivec2(2, 1)];
+        draw_move_path(&s, &path);
+        // drawn below the cursor, above the tiles
+        s.draw_sprite("cursor", vec2(0., 0.), Z_CURSOR, WHITE);
+        s.draw_rect(vec2(0., 0.), 16., 16., Z_GROUND, WHITE);
+
+        let mut recorder = Recorder::default();
+        let mut s = s;
+        s.flush_draw_buffer(&mut recorder);
+        let arrows = recorder
+            .commands
+            .iter()
+            .filter(|c| c.z_level == Z_MOVE_ARROW)
+            .map(|c| match &c.kind {
+                DrawKind::Sprite {
+                    name: Some(name), ..
+                } => (name.as_str(), c.pos),
+                kind => panic!("{:?} is no arrow", kind),
+            })
+            .collect_vec();
+        assert_eq!(
+            vec![
+                ("arrow_we", vec2(16., 0.)),
+                ("arrow_ws", vec2(32., 0.)),
+                ("arrow_s", vec2(32., 16.)),
+            ],
+            arrows
+        );
+        assert_eq!(Z_GROUND, recorder.commands[0].z_level);
+        assert_eq!(Z_CURSOR, recorder.commands.last().unwrap().z_level);
+    }
+
+    #[test]
+    fn dijkstra_map_is_drawn_over_the_tiles() {
+        let mut s = game_state();
+        let mut costs = Grid::new(4, 4, 0);
+        costs[(2, 1)] = 3;
+        draw_dijkstra_map(&s, &costs);
+        s.draw_rect(vec2(0., 0.), 16., 16., Z_GROUND, WHITE);
+
+        let mut recorder = Recorder::default();
+        s.flush_draw_buffer(&mut recorder);
+        let overlay = &recorder.commands[1..];
+        assert_eq!(16 + 1, overlay.len());
+        assert!(overlay.iter().all(|c| c.z_level == Z_DIJKSTRA_DEBUG));
+        let texts = overlay
+            .iter()
+            .filter_map(|c| match &c.kind {
+                DrawKind::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(vec!["3"], texts);
+    }
+
+    #[test]
+    fn big_batches_are_split_into_chunks() {
+        let mut batch = SpriteBatch::default();
+        let src = Rect::new(16., 32., 16., 16.);
+        for i in 0..BATCH_QUADS + 1 {
+            batch.push(vec2(i as f32, 0.), src, WHITE, vec2(64., 64.));
+        }
+        assert_eq!((BATCH_QUADS + 1, 2), (batch.len(), batch.chunks()));
+        // the first quad of every chunk starts at index 0 again
+        let last_chunk = &batch.indices[BATCH_QUADS * 6..];
+        assert_eq!(&[0, 1, 2, 0, 2, 3], last_chunk);
+        let uv = batch.vertices[0].uv;
+        assert!((uv - vec2(0.25, 0.5)).abs().max_element() < 0.01);
+    }
+
+    #[test]
+    fn tile_layers_are_built_once() {
+        let mut s = game_state();
+        rebuild_tile_sprites(&mut s);
+        s.grids.terrain[(1, 1)] = TerrainType::Forest;
+        rebuild_tile_sprites(&mut s);
+        let batches = |s: &mut GameState| {
+            draw_tiles(s);
+            let mut recorder = Recorder::default();
+            s.flush_draw_buffer(&mut recorder);
+            recorder
+                .commands
+                .into_iter()
+                .map(|c| match c.kind {
+                    DrawKind::Batch(batch) => (c.z_level, batch),
+                    kind => panic!("{:?} is no tile layer", kind),
+                })
+                .collect_vec()
+        };
+        let first = batches(&mut s);
+        assert_eq!(Z_GROUND, first[0].0);
+        assert_eq!((16, 1), (first[0].1.len(), first[1].1.len()));
+        // the next frame draws the same vertices
+        let second = batches(&mut s);
+        assert!(Arc::ptr_eq(&first[0].1, &second[0].1));
+        // hex maps shift every other row, so the tiles are moved
+        s.grids.topology = Topology::Hex;
+        let hex = batches(&mut s);
+        assert!(!Arc::ptr_eq(&first[0].1, &hex[0].1));
+        assert_ne!(first[0].1, hex[0].1);
+    }
+
+    #[test]
+    fn only_what_is_on_screen_is_drawn() {
+        let mut s = game_state();
+        s.grids.ground = Grid::new(40, 40, GroundType::Ground);
+        s.grids.terrain = Grid::new(40, 40, TerrainType::None);
+        rebuild_tile_sprites(&mut s);
+        let def = &s.entity_defs["red_tank"];
+        for pos in [ivec2(1, 1), ivec2(20, 20), ivec2(39, 39)] {
+            s.entities.insert(Actor::from_def("red_tank", def, pos));
+        }
+        // a bit less than the middle chunk, 16 tiles from 256 to 512 pixels
+        s.view = Some(Rect::new(300., 300., 100., 100.));
+        draw_tiles(&mut s);
+        draw_actors(&mut s);
+
+        let mut recorder = Recorder::default();
+        s.flush_draw_buffer(&mut recorder);
+        let tiles = recorder
+            .commands
+            .iter()
+            .filter_map(|c| match &c.kind {
+                DrawKind::Batch(batch) => Some(batch.len()),
+                _ => None,
+            })
+            .collect_vec();
+        assert_eq!(vec![16 * 16], tiles);
+        let units = recorder.sprites("red_tank");
+        assert_eq!(1, units.len());
+        assert_eq!(vec2(320., 320.), units[0].pos);
+
+        // without a view everything is drawn, like in the other tests
+        s.view = None;
+        draw_tiles(&mut s);
+        draw_actors(&mut s);
+        let mut recorder = Recorder::default();
+        s.flush_draw_buffer(&mut recorder);
+        assert_eq!(3, recorder.sprites("red_tank").len());
+        assert_eq!(9, recorder.commands.len() - 3);
+    }
+}
@@ -0,0 +1,208 @@
+/// map editing: painting tiles, placing units and resizing
+/// kept free of rendering, the editor ui in main only calls into this
+use crate::*;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Brush {
+    Ground(GroundType),
+    Terrain(TerrainType),
+    /// a unit by its name in entities_def.json
+    Unit(String),
+    /// removes the unit on the tile, or the terrain if there is none
+    Erase,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush::Ground(GroundType::Ground)
+    }
+}
+
+impl Brush {
+    /// every brush, units in the order of `unit_names`
+    pub fn all(unit_names: &[String]) -> Vec<Brush> {
+        let mut brushes = vec![
+            Brush::Ground(GroundType::Ground),
+            Brush::Ground(GroundType::Water),
+            Brush::Terrain(TerrainType::Street),
+            Brush::Terrain(TerrainType::Forest),
+        ];
+        brushes.extend(unit_names.iter().cloned().map(Brush::Unit));
+        brushes.push(Brush::Erase);
+        brushes
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Brush::Ground(ground) => format!("{:?}", ground),
+            Brush::Terrain(terrain) => format!("{:?}", terrain),
+            Brush::Unit(name) => name.clone(),
+            Brush::Erase => "Erase".to_string(),
+        }
+    }
+}
+
+/// paints `brush` onto `pos`, returns false if nothing changed
+pub fn apply_brush(
+    grids: &mut Grids,
+    entities: &mut SlotMap<ActorKey, Actor>,
+    entity_defs: &HashMap<String, EntityDef>,
+    brush: &Brush,
+    pos: IVec2,
+) -> bool {
+    if !grids.ground.is_valid(pos) {
+        return false;
+    }
+    let unit = actor_at_pos(entities, pos);
+    match brush {
+        Brush::Ground(ground) => replace(&mut grids.ground[pos], *ground),
+        Brush::Terrain(terrain) => replace(&mut grids.terrain[pos], *terrain),
+        Brush::Unit(name) => {
+            let Some(def) = entity_defs.get(name) else {
+                return false;
+            };
+            if unit.is_some_and(|unit| entities[unit].sprite_name == *name) {
+                return false;
+            }
+            if let Some(unit) = unit {
+                entities.remove(unit);
+            }
+            entities.insert(Actor::from_def(name, def, pos));
+            true
+        }
+        Brush::Erase => match unit {
+            Some(unit) => entities.remove(unit).is_some(),
+            None => replace(&mut grids.terrain[pos], TerrainType::None),
+        },
+    }
+}
+
+/// sets `value`, returns true if it was different before
+fn replace<T: PartialEq>(value: &mut T, new: T) -> bool {
+    let changed = *value != new;
+    *value = new;
+    changed
+}
+
+/// grows or shrinks the map at the right and bottom edge
+/// new tiles are plain ground, units outside of the map are removed
+pub fn resize_map(
+    grids: &mut Grids,
+    entities: &mut SlotMap<ActorKey, Actor>,
+    width: i32,
+    height: i32,
+) {
+    grids.ground = resized(&grids.ground, width, height);
+    grids.terrain = resized(&grids.terrain, width, height);
+    entities.retain(|_, actor| grids.ground.is_valid(actor.pos));
+}
+
+fn resized<T: Clone + Default>(grid: &Grid<T>, width: i32, height: i32) -> Grid<T> {
+    Grid::filled_with(width, height, |x, y| {
+        let pos = ivec2(x, y);
+        if grid.is_valid(pos) {
+            grid[pos].clone()
+        } else {
+            T::default()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (Grids, SlotMap<ActorKey, Actor>, HashMap<String, EntityDef>) {
+        let grids = Grids {
+            ground: Grid::new(4, 3, GroundType::Ground),
+            terrain: Grid::new(4, 3, TerrainType::None),
+            topology: Topology::default(),
+        };
+        (grids, SlotMap::with_key(), entity_defs().unwrap())
+    }
+
+    #[test]
+    fn paint_and_erase() {
+        let (mut grids, mut entities, defs) = setup();
+        let pos = ivec2(1, 1);
+        let forest = Brush::Terrain(TerrainType::Forest);
+        assert!(apply_brush(&mut grids, &mut entities, &defs, &forest, pos));
+        assert!(!apply_brush(&mut grids, &mut entities, &defs, &forest, pos));
+        assert_eq!(TerrainType::Forest, grids.terrain[pos]);
+
+        let tank = Brush::Unit("red_tank".to_string());
+        assert!(apply_brush(&mut grids, &mut entities, &defs, &tank, pos));
+        let infantry = Brush::Unit("blue_infantry".to_string());
+        assert!(apply_brush(
+            &mut grids,
+            &mut entities,
+            &defs,
+            &infantry,
+            pos
+        ));
+        assert_eq!(1, entities.len());
+        assert_eq!(Team::Blue, entities.values().next().unwrap().team);
+        let unknown = Brush::Unit("dragon".to_string());
+        assert!(!apply_brush(
+            &mut grids,
+            &mut entities,
+            &defs,
+            &unknown,
+            pos
+        ));
+
+        // units go first, then the terrain
+        assert!(apply_brush(
+            &mut grids,
+            &mut entities,
+            &defs,
+            &Brush::Erase,
+            pos
+        ));
+        assert!(entities.is_empty());
+        assert_eq!(TerrainType::Forest, grids.terrain[pos]);
+        assert!(apply_brush(
+            &mut grids,
+            &mut entities,
+            &defs,
+            &Brush::Erase,
+            pos
+        ));
+        assert_eq!(TerrainType::None, grids.terrain[pos]);
+
+        assert!(!apply_brush(
+            &mut grids,
+            &mut entities,
+            &defs,
+            &forest,
+            ivec2(4, 0)
+        ));
+    }
+
+    #[test]
+    fn resize_keeps_the_top_left() {
+        let (mut grids, mut entities, defs) = setup();
+        let water = Brush::Ground(GroundType::Water);
+        apply_brush(&mut grids, &mut entities, &defs, &water, ivec2(0, 0));
+        apply_brush(&mut grids, &mut entities, &defs, &water, ivec2(3, 2));
+        let tank = Brush::Unit("red_tank".to_string());
+        apply_brush(&mut grids, &mut entities, &defs, &tank, ivec2(3, 0));
+
+        resize_map(&mut grids, &mut entities, 6, 2);
+        assert_eq!((6, 2), (grids.ground.width, grids.terrain.height));
+        assert_eq!(GroundType::Water, grids.ground[(0, 0)]);
+        assert_eq!(GroundType::Ground, grids.ground[(5, 1)]);
+        assert_eq!(1, entities.len());
+
+        resize_map(&mut grids, &mut entities, 3, 2);
+        assert!(entities.is_empty());
+    }
+
+    #[test]
+    fn map_file_round_trip() {
+        let map = GameMap::load("assets/comfy_wars.ldtk").unwrap();
+        let loaded = GameMap::from_json(&map.to_json(), &entity_defs().unwrap()).unwrap();
+        assert_eq!(map.to_json(), loaded.to_json());
+        assert_eq!(map.actors.len(), loaded.actors.len());
+    }
+}
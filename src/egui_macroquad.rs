@@ -82,6 +82,17 @@ pub fn draw() {
     get_egui().draw()
 }
 
+/// makes a macroquad texture usable in egui, e.g. for `egui::Image`
+/// None on the metal backend, egui-miniquad only knows opengl textures there
+pub fn texture_id(texture: &Texture2D) -> Option<egui::TextureId> {
+    let gl = unsafe { get_internal_gl() };
+    match unsafe { gl.quad_context.texture_raw_id(texture.raw_miniquad_id()) } {
+        mq::RawId::OpenGl(id) => Some(egui::TextureId::User(id as u64)),
+        #[cfg(target_vendor = "apple")]
+        mq::RawId::Metal(_) => None,
+    }
+}
+
 // Intended to be used only if you recreate the window, making the old EGUI instance invalid.
 #[doc(hidden)]
 pub fn reset_egui() {
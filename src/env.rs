@@ -0,0 +1,339 @@
+/// gym style environment for training agents on the game rules
+///
+/// the agent controls one team and acts one unit at a time,
+/// the other team is played by a `Controller` in between.
+///
+/// the game is also a library, so this works from other crates:
+///
+/// ```
+/// use comfy_wars::ai::Controller;
+/// use comfy_wars::data::{entity_defs, GameMap};
+/// use comfy_wars::env::Env;
+/// use comfy_wars::game::Team;
+///
+/// let map = GameMap::from_ascii("a...b\n\na: blue_infantry\nb: red_infantry", &entity_defs()?)?;
+/// let mut env = Env::new(Team::Blue, Controller::default()).with_max_rounds(10);
+/// let mut observation = env.reset(&map, 42);
+/// while observation.unit.is_some() {
+///     let action = env.legal_actions()[0];
+///     let step = env.step(action)?;
+///     observation = step.observation;
+/// }
+/// assert!(env.legal_actions().is_empty());
+/// # anyhow::Ok(())
+/// ```
+use crate::ai::Controller;
+use crate::sim::Match;
+use crate::util::Rng;
+use crate::*;
+
+/// names of the observation layers, in order
+pub const CHANNELS: [&str; 12] = [
+    "water",
+    "street",
+    "forest",
+    "own_hp",
+    "enemy_hp",
+    "own_infantry",
+    "own_tank",
+    "enemy_infantry",
+    "enemy_tank",
+    "own_has_moved",
+    "acting_unit",
+    "acting_unit_reachable",
+];
+
+/// reward for winning, the loser gets the negative
+pub const WIN_REWARD: f32 = 1.;
+
+/// dense row major tensor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tensor {
+    /// channels, height, width
+    pub shape: [usize; 3],
+    pub data: Vec<f32>,
+}
+
+impl Tensor {
+    pub fn get(&self, channel: usize, x: i32, y: i32) -> f32 {
+        let [_, h, w] = self.shape;
+        self.data[channel * h * w + y as usize * w + x as usize]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub tensor: Tensor,
+    /// the unit that the next action is for, None once the episode is done
+    pub unit: Option<ActorKey>,
+    pub round: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub observation: Observation,
+    pub reward: f32,
+    pub done: bool,
+}
+
+pub struct Env {
+    agent: Team,
+    opponent: Controller,
+    max_rounds: i32,
+    state: Match,
+    rng: Rng,
+    /// agent units which still have to act this round
+    pending: Vec<ActorKey>,
+}
+
+impl Env {
+    pub fn new(agent: Team, opponent: Controller) -> Self {
+        Self {
+            agent,
+            opponent,
+            max_rounds: 50,
+            state: Match::new(Grids::default(), []),
+            rng: Rng::new(0),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn with_max_rounds(mut self, max_rounds: i32) -> Self {
+        self.max_rounds = max_rounds;
+        self
+    }
+
+    /// starts a new episode, the agent moves first
+    pub fn reset(&mut self, map: &GameMap, seed: u64) -> Observation {
+        self.state = Match::new(map.grids.clone(), map.actors.iter().cloned());
+        self.rng = Rng::new(seed);
+        self.start_round();
+        self.observe()
+    }
+
+    /// actions the acting unit may take, empty once the episode is done
+    pub fn legal_actions(&self) -> Vec<UnitAction> {
+        match self.pending.first() {
+            Some(unit) => legal_actions(&self.state.grids, &self.state.entities, *unit),
+            None => Vec::new(),
+        }
+    }
+
+    /// executes the action for the acting unit
+    /// once all agent units have acted, the opponent plays its turn
+    /// the reward is the hp balance of the step, scaled so a full unit is worth 1
+    pub fn step(&mut self, action: UnitAction) -> Result<Step> {
+        if self.is_done() {
+            anyhow::bail!("episode is done, call reset");
+        }
+        if !self.legal_actions().contains(&action) {
+            anyhow::bail!("illegal action {:?}", action);
+        }
+        let own_before = self.team_hp(self.agent);
+        let enemy_before = self.team_hp(self.agent.other());
+
+        let unit = self.pending.remove(0);
+        self.state.apply(unit, action);
+        // units killed this step can't act anymore
+        self.pending
+            .retain(|unit| self.state.entities.contains_key(*unit));
+
+        if self.pending.is_empty() && !self.state.is_over() {
+            for actor in self.state.entities.values_mut() {
+                actor.has_moved = false;
+            }
+            self.state
+                .play_turn(self.agent.other(), &mut self.opponent, &mut self.rng);
+            if !self.state.is_over() {
+                self.start_round();
+            }
+        }
+
+        let own_lost = own_before - self.team_hp(self.agent);
+        let enemy_lost = enemy_before - self.team_hp(self.agent.other());
+        let mut reward = (enemy_lost - own_lost) as f32 / HP_MAX as f32;
+        match self.state.winner() {
+            Some(team) if team == self.agent => reward += WIN_REWARD,
+            Some(_) => reward -= WIN_REWARD,
+            None => {}
+        }
+        Ok(Step {
+            observation: self.observe(),
+            reward,
+            done: self.is_done(),
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state.is_over() || self.state.round > self.max_rounds
+    }
+
+    pub fn state(&self) -> &Match {
+        &self.state
+    }
+
+    fn start_round(&mut self) {
+        self.state.round += 1;
+        if self.state.round > self.max_rounds {
+            self.pending.clear();
+            return;
+        }
+        self.pending = self
+            .state
+            .entities
+            .iter()
+            .filter(|(_k, a)| a.team == self.agent)
+            .map(|(k, _a)| k)
+            .collect_vec();
+    }
+
+    fn team_hp(&self, team: Team) -> i32 {
+        self.state
+            .entities
+            .values()
+            .filter(|a| a.team == team)
+            .map(|a| a.hp.max(0))
+            .sum()
+    }
+
+    pub fn observe(&self) -> Observation {
+        let grids = &self.state.grids;
+        let entities = &self.state.entities;
+        let (w, h) = (grids.ground.width, grids.ground.height);
+        let unit = if self.is_done() {
+            None
+        } else {
+            self.pending.first().copied()
+        };
+
+        let flag = |b: bool| if b { 1. } else { 0. };
+        let mut layers: Vec<Grid<f32>> = vec![
+            Grid::filled_with(w, h, |x, y| flag(grids.ground[(x, y)] == GroundType::Water)),
+            Grid::filled_with(w, h, |x, y| {
+                flag(grids.terrain[(x, y)] == TerrainType::Street)
+            }),
+            Grid::filled_with(w, h, |x, y| {
+                flag(grids.terrain[(x, y)] == TerrainType::Forest)
+            }),
+        ];
+
+        let own = |a: &Actor| a.team == self.agent;
+        let hp = |a: &Actor| a.hp as f32 / HP_MAX as f32;
+        let unit_layers: [&dyn Fn(&Actor) -> f32; 7] = [
+            &|a| if own(a) { hp(a) } else { 0. },
+            &|a| if own(a) { 0. } else { hp(a) },
+            &|a| flag(own(a) && a.unit_type == UnitType::Infantry),
+            &|a| flag(own(a) && a.unit_type == UnitType::Tank),
+            &|a| flag(!own(a) && a.unit_type == UnitType::Infantry),
+            &|a| flag(!own(a) && a.unit_type == UnitType::Tank),
+            &|a| flag(own(a) && a.has_moved),
+        ];
+        for f in unit_layers {
+            let mut grid = Grid::new(w, h, 0.);
+            for actor in entities.values() {
+                grid[actor.pos] = f(actor);
+            }
+            layers.push(grid);
+        }
+
+        let mut acting = Grid::new(w, h, 0.);
+        let mut reachable = Grid::new(w, h, 0.);
+        if let Some(unit) = unit {
+            acting[entities[unit].pos] = 1.;
+            let reach = Reachability::new(grids, entities, unit);
+            reachable = Grid::filled_with(w, h, |x, y| flag(reach.is_reachable(ivec2(x, y))));
+        }
+        layers.push(acting);
+        layers.push(reachable);
+        debug_assert_eq!(CHANNELS.len(), layers.len());
+
+        let shape = [layers.len(), h as usize, w as usize];
+        let data = layers
+            .into_iter()
+            .flat_map(|layer| layer.into_iter_values())
+            .collect_vec();
+        Observation {
+            tensor: Tensor { shape, data },
+            unit,
+            round: self.state.round,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> GameMap {
+        let text = "
+            ~.....
+            .b....
+            .b..r.
+            ......
+
+            b: blue_infantry
+            r: red_infantry
+            ";
+        GameMap::from_ascii(text, &entity_defs().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn observation_layers() {
+        let mut env = Env::new(Team::Blue, Controller::default());
+        let obs = env.reset(&map(), 1);
+        assert_eq!([CHANNELS.len(), 4, 6], obs.tensor.shape);
+        assert_eq!(1., obs.tensor.get(0, 0, 0));
+        assert_eq!(0., obs.tensor.get(0, 1, 0));
+        // own hp
+        assert_eq!(1., obs.tensor.get(3, 1, 1));
+        assert_eq!(0., obs.tensor.get(3, 4, 2));
+        // enemy hp
+        assert_eq!(1., obs.tensor.get(4, 4, 2));
+        let acting = env.state().entities[obs.unit.unwrap()].pos;
+        assert_eq!(1., obs.tensor.get(10, acting.x, acting.y));
+    }
+
+    #[test]
+    fn episode_terminates_with_reward() {
+        let mut env = Env::new(Team::Blue, Controller::default()).with_max_rounds(30);
+        env.reset(&map(), 3);
+        let mut total = 0.;
+        let mut steps = 0;
+        loop {
+            // greedy: attack when possible
+            let actions = env.legal_actions();
+            let action = actions
+                .iter()
+                .find(|a| a.attack.is_some())
+                .unwrap_or(&actions[0]);
+            let step = env.step(*action).unwrap();
+            total += step.reward;
+            steps += 1;
+            if step.done {
+                assert!(step.observation.unit.is_none());
+                break;
+            }
+            assert!(steps < 1000);
+        }
+        assert!(env.legal_actions().is_empty());
+        assert!(env
+            .step(UnitAction {
+                move_to: ivec2(1, 1),
+                attack: None
+            })
+            .is_err());
+        // two on one, the agent has to come out ahead
+        assert!(total > 0.);
+    }
+
+    #[test]
+    fn illegal_action_is_rejected() {
+        let mut env = Env::new(Team::Blue, Controller::default());
+        env.reset(&map(), 1);
+        let result = env.step(UnitAction {
+            move_to: ivec2(0, 0),
+            attack: None,
+        });
+        assert!(result.is_err());
+    }
+}
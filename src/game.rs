@@ -2,10 +2,22 @@ use crate::util::*;
 /// some gameplay functions
 use crate::*;
 
+/// size of a tile in world pixels
+pub const GRIDSIZE: i32 = 16;
+
 pub const ENEMY_TEAM: Team = Team::Red;
 pub const PLAYER_TEAM: Team = Team::Blue;
 
-#[derive(Debug, Serialize, Deserialize)]
+slotmap::new_key_type! {
+    pub struct ActorKey;
+}
+
+/// top left corner of a tile in world pixels
+pub fn game_to_world(v: IVec2) -> Vec2 {
+    vec2((v.x * GRIDSIZE) as f32, (v.y * GRIDSIZE) as f32)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Actor {
     #[serde(with = "IVec2Proxy")]
     pub pos: IVec2,
@@ -22,7 +34,7 @@ pub struct Actor {
 
 pub const HP_MAX: i32 = 10;
 
-#[derive(DeJson, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(DeJson, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Team {
     Blue,
     Red,
@@ -35,7 +47,7 @@ pub enum UnitType {
 }
 
 /// used for determining movement cost
-#[derive(Default, DeJson, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Default, DeJson, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum GroundType {
     #[default]
     Ground,
@@ -43,7 +55,7 @@ pub enum GroundType {
 }
 
 /// used for determining movement cost
-#[derive(Default, DeJson, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Default, DeJson, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum TerrainType {
     #[default]
     None,
@@ -51,24 +63,261 @@ pub enum TerrainType {
     Forest,
 }
 
+/// forests can be looked into but not through
+pub fn blocks_sight(terrain: &TerrainType) -> bool {
+    *terrain == TerrainType::Forest
+}
+
+/// how far units can see, in tiles
+pub const VISION_RANGE: i32 = 6;
+
+/// damage dealt by a single attack
+pub const ATTACK_DAMAGE: i32 = 5;
+
+impl Actor {
+    pub fn from_def(def_name: &str, def: &EntityDef, pos: IVec2) -> Self {
+        Actor {
+            pos,
+            draw_pos: vec2((pos.x * GRIDSIZE) as f32, (pos.y * GRIDSIZE) as f32),
+            sprite_coords: ivec2(def.sprite.x, def.sprite.y),
+            sprite_name: def_name.to_string(),
+            team: def.team,
+            unit_type: def.unit_type,
+            hp: HP_MAX,
+            has_moved: false,
+        }
+    }
+}
+
+impl Team {
+    pub fn other(self) -> Team {
+        match self {
+            Team::Blue => Team::Red,
+            Team::Red => Team::Blue,
+        }
+    }
+}
+
 /// returns units of other team which are in attack range
-pub fn enemies_in_range(s: &GameState, me: ActorKey) -> Vec<(ActorKey, IVec2)> {
-    let pos = s.entities[me].pos;
-    let my_team = s.entities[me].team;
-    let neighbors = get_neighbors(pos, &s.grids.ground);
+pub fn enemies_in_range(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    me: ActorKey,
+) -> Vec<(ActorKey, IVec2)> {
+    enemies_in_range_from(grids, entities, me, entities[me].pos)
+}
+
+/// returns units of other team which would be in attack range
+/// if the unit was standing at `pos`
+pub fn enemies_in_range_from(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    me: ActorKey,
+    pos: IVec2,
+) -> Vec<(ActorKey, IVec2)> {
+    let my_team = entities[me].team;
+    let neighbors = grids.ground.neighbors(pos, grids.topology);
     let in_range = neighbors
         .iter()
-        .filter_map(|pos| actor_at_pos(s, *pos).map(|index| (index, *pos)));
+        .filter_map(|pos| actor_at_pos(entities, *pos).map(|index| (index, *pos)));
     in_range
-        .filter(|(index, _)| s.entities[*index].team != my_team)
+        .filter(|(index, _)| entities[*index].team != my_team && *index != me)
         .collect_vec()
 }
 
-pub fn actor_at_pos(s: &GameState, pos: IVec2) -> Option<ActorKey> {
-    for (index, actor) in s.entities.iter() {
+pub fn actor_at_pos(entities: &SlotMap<ActorKey, Actor>, pos: IVec2) -> Option<ActorKey> {
+    for (index, actor) in entities.iter() {
         if actor.pos == pos {
             return Some(index);
         }
     }
     None
 }
+
+/// what an attack will do, known before it happens
+/// every hit deals `ATTACK_DAMAGE`, the rules have no counter attack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttackForecast {
+    pub damage: i32,
+    /// percent of the damage the defender's tile blocks, 0 since terrain gives no defense
+    pub terrain_defense: i32,
+    pub defender_hp_after: i32,
+    /// always 0, the defender doesn't strike back
+    pub counter_damage: i32,
+    pub attacker_hp_after: i32,
+}
+
+pub fn forecast_attack(
+    entities: &SlotMap<ActorKey, Actor>,
+    attacker: ActorKey,
+    defender: ActorKey,
+) -> AttackForecast {
+    let (attacker, defender) = (&entities[attacker], &entities[defender]);
+    let damage = ATTACK_DAMAGE;
+    AttackForecast {
+        damage,
+        terrain_defense: 0,
+        defender_hp_after: (defender.hp - damage).max(0),
+        counter_damage: 0,
+        attacker_hp_after: attacker.hp,
+    }
+}
+
+/// applies an attack instantly, a defender that dies is removed
+pub fn resolve_attack(
+    entities: &mut SlotMap<ActorKey, Actor>,
+    attacker: ActorKey,
+    defender: ActorKey,
+) -> AttackForecast {
+    let forecast = forecast_attack(entities, attacker, defender);
+    entities[defender].hp = forecast.defender_hp_after;
+    if entities[defender].hp <= 0 {
+        entities.remove(defender);
+    }
+    forecast
+}
+
+pub fn movement_cost<'a>(
+    grids: &'a Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    team: Team,
+) -> impl Fn(IVec2) -> i32 + 'a {
+    let blocked: HashSet<IVec2> = entities
+        .iter()
+        .filter_map(|(_i, e)| if e.team != team { Some(e.pos) } else { None })
+        .collect();
+
+    let cost_function = move |pos| -> i32 {
+        if blocked.contains(&pos) {
+            return 9999;
+        }
+        let ground = *grids.ground.get_clamped_v(pos);
+        let terrain = *grids.terrain.get_clamped_v(pos);
+        use GroundType as G;
+        use TerrainType as T;
+        match (ground, terrain) {
+            (G::Water, _) => 9999,
+            (G::Ground, T::None) => 2,
+            (G::Ground, T::Street) => 1,
+            (G::Ground, T::Forest) => 3,
+        }
+    };
+    cost_function
+}
+
+/// what a single unit does during its turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitAction {
+    #[serde(with = "IVec2Proxy")]
+    pub move_to: IVec2,
+    /// position of the enemy to attack after moving
+    #[serde(with = "option_ivec2_proxy")]
+    pub attack: Option<IVec2>,
+}
+
+/// how much movement cost a unit can spend per turn
+pub const MOVE_POINTS: i32 = 8;
+
+/// path the unit takes to `goal`, starting at its current position
+/// empty if the goal can't be reached this turn or another unit stands there
+pub fn path_to(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    me: ActorKey,
+    goal: IVec2,
+) -> Vec<IVec2> {
+    if actor_at_pos(entities, goal).is_some_and(|other| other != me) {
+        return Vec::new();
+    }
+    astar(
+        grids.ground.width,
+        grids.ground.height,
+        grids.topology,
+        entities[me].pos,
+        goal,
+        Some(MOVE_POINTS),
+        movement_cost(grids, entities, entities[me].team),
+    )
+    .unwrap_or_default()
+}
+
+/// path to the tile closest to any of `goals` where the unit may stop this turn
+/// allies can be passed but not stopped on
+/// goals further away than `max_goal_distance` are ignored,
+/// which makes the unit stay where it is
+/// among equally close tiles the cheapest to reach wins, so staying is preferred
+pub fn path_towards(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    me: ActorKey,
+    goals: &[IVec2],
+    max_goal_distance: Option<i32>,
+) -> Vec<IVec2> {
+    let start = entities[me].pos;
+    let reach = Reachability::new(grids, entities, me);
+    let goal_distance = dijkstra_map(
+        grids.ground.width,
+        grids.ground.height,
+        grids.topology,
+        goals,
+        max_goal_distance,
+        movement_cost(grids, entities, entities[me].team),
+    );
+    let destination = reach
+        .stoppable_tiles()
+        .filter(|pos| goal_distance.is_reached(*pos))
+        .min_by_key(|pos| (goal_distance.cost[*pos], reach.moves.cost[*pos]))
+        .unwrap_or(start);
+    reach.path_to(destination).unwrap_or_default()
+}
+
+/// every combination of reachable free tile and attack from there
+pub fn legal_actions(
+    grids: &Grids,
+    entities: &SlotMap<ActorKey, Actor>,
+    me: ActorKey,
+) -> Vec<UnitAction> {
+    Reachability::new(grids, entities, me).actions()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ascii_map::test_board;
+
+    fn duel(attacker_hp: i32, defender_hp: i32) -> (SlotMap<ActorKey, Actor>, ActorKey, ActorKey) {
+        let (_, mut entities) = test_board("ad\n\na: blue_infantry\nd: red_infantry");
+        let attacker = actor_at_pos(&entities, ivec2(0, 0)).unwrap();
+        let defender = actor_at_pos(&entities, ivec2(1, 0)).unwrap();
+        entities[attacker].hp = attacker_hp;
+        entities[defender].hp = defender_hp;
+        (entities, attacker, defender)
+    }
+
+    #[test]
+    fn attacks_deal_flat_damage() {
+        let (mut entities, attacker, defender) = duel(HP_MAX, HP_MAX);
+        let forecast = forecast_attack(&entities, attacker, defender);
+        assert_eq!(ATTACK_DAMAGE, forecast.damage);
+        assert_eq!(0, forecast.terrain_defense);
+        assert_eq!(HP_MAX - ATTACK_DAMAGE, forecast.defender_hp_after);
+        // no counter attack
+        assert_eq!(0, forecast.counter_damage);
+        assert_eq!(HP_MAX, forecast.attacker_hp_after);
+
+        assert_eq!(forecast, resolve_attack(&mut entities, attacker, defender));
+        assert_eq!(forecast.defender_hp_after, entities[defender].hp);
+        assert_eq!(HP_MAX, entities[attacker].hp);
+    }
+
+    #[test]
+    fn dead_units_are_removed() {
+        let (mut entities, attacker, defender) = duel(1, 3);
+        let forecast = resolve_attack(&mut entities, attacker, defender);
+        assert_eq!(0, forecast.defender_hp_after);
+        assert!(!entities.contains_key(defender));
+        // even a nearly dead attacker is safe
+        assert_eq!(1, entities[attacker].hp);
+    }
+}
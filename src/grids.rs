@@ -192,7 +192,7 @@ impl<T: Clone> Grid<T> {
     }
 
     pub fn pack(fill: T, grids: Vec<Grid<T>>) -> Grid<T> {
-        assert!(grids.len() > 0);
+        assert!(!grids.is_empty());
 
         let mut size = IVec2::ZERO;
 
@@ -219,7 +219,7 @@ impl<T: Clone> Grid<T> {
     /// Returns an iterator over the rows of the grid.
     ///
     /// ```
-    /// use grids::Grid;
+    /// use comfy_wars::grids::Grid;
     ///
     /// let mut grid = Grid::new(3, 2, 0);
     ///
@@ -459,6 +459,7 @@ fn test_row_iter_single_row() {
 }
 
 #[test]
+#[allow(clippy::useless_vec)]
 fn test_row_iter_multiple_rows() {
     let grid = Grid::filled_with(3, 3, |x, y| x + y);
     let mut row_iter = grid.row_iter();
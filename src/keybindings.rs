@@ -0,0 +1,628 @@
+/// named input actions and the keys, mouse buttons and gamepad buttons bound to them
+/// bindings are read from a json file, actions missing there keep their defaults
+use anyhow::Result;
+use macroquad::prelude::{
+    get_last_key_pressed, is_key_down, is_key_pressed, is_key_released, is_mouse_button_down,
+    is_mouse_button_pressed, is_mouse_button_released, KeyCode, MouseButton,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+
+/// where the bindings are loaded from and saved to, relative to the working directory
+pub const KEYBINDINGS_PATH: &str = "keybindings.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    /// click on a tile or unit
+    Select,
+    /// select, move or attack from the keyboard
+    Confirm,
+    Cancel,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    /// select the next unit that hasn't moved yet
+    NextUnit,
+    EndTurn,
+    ContextMenu,
+    /// pan the camera while held and dragging
+    DragPan,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    ToggleThreat,
+    ToggleDijkstraMap,
+    ToggleAiMap,
+    ToggleFov,
+    SaveGame,
+    LoadGame,
+    ShowKeybindings,
+    ToggleEditor,
+}
+
+impl Action {
+    pub const ALL: [Action; 23] = [
+        Action::Select,
+        Action::Confirm,
+        Action::Cancel,
+        Action::CursorUp,
+        Action::CursorDown,
+        Action::CursorLeft,
+        Action::CursorRight,
+        Action::NextUnit,
+        Action::EndTurn,
+        Action::ContextMenu,
+        Action::DragPan,
+        Action::PanUp,
+        Action::PanDown,
+        Action::PanLeft,
+        Action::PanRight,
+        Action::ToggleThreat,
+        Action::ToggleDijkstraMap,
+        Action::ToggleAiMap,
+        Action::ToggleFov,
+        Action::SaveGame,
+        Action::LoadGame,
+        Action::ShowKeybindings,
+        Action::ToggleEditor,
+    ];
+
+    fn default_bindings(self) -> Vec<Binding> {
+        use KeyCode::*;
+        let keys = |keys: &[KeyCode]| keys.iter().map(|k| Binding::key(*k)).collect();
+        let with_pad = |mut bindings: Vec<Binding>, button| {
+            bindings.push(Binding::Pad(button));
+            bindings
+        };
+        match self {
+            Action::Select => vec![Binding::Mouse(MouseButton::Left)],
+            Action::Confirm => with_pad(keys(&[Enter, KpEnter, Space]), PadButton::South),
+            Action::Cancel => with_pad(keys(&[Escape]), PadButton::East),
+            Action::CursorUp => with_pad(keys(&[Up, W]), PadButton::DPadUp),
+            Action::CursorDown => with_pad(keys(&[Down, S]), PadButton::DPadDown),
+            Action::CursorLeft => with_pad(keys(&[Left, A]), PadButton::DPadLeft),
+            Action::CursorRight => with_pad(keys(&[Right, D]), PadButton::DPadRight),
+            Action::NextUnit => with_pad(keys(&[Q]), PadButton::RightShoulder),
+            Action::EndTurn => with_pad(keys(&[End]), PadButton::Start),
+            Action::ContextMenu => vec![Binding::Mouse(MouseButton::Right)],
+            Action::DragPan => vec![Binding::Mouse(MouseButton::Middle)],
+            Action::PanUp => vec![Binding::shift(W)],
+            Action::PanDown => vec![Binding::shift(S)],
+            Action::PanLeft => vec![Binding::shift(A)],
+            Action::PanRight => vec![Binding::shift(D)],
+            Action::ToggleThreat => with_pad(keys(&[T]), PadButton::North),
+            Action::ToggleDijkstraMap => keys(&[L]),
+            Action::ToggleAiMap => keys(&[M]),
+            Action::ToggleFov => keys(&[V]),
+            Action::SaveGame => keys(&[F5]),
+            Action::LoadGame => keys(&[F9]),
+            Action::ShowKeybindings => keys(&[K]),
+            Action::ToggleEditor => keys(&[F2]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Binding {
+    /// a key, with or without shift held
+    Key {
+        #[serde(with = "KeyCodeProxy")]
+        key: KeyCode,
+        #[serde(default)]
+        shift: bool,
+    },
+    Mouse(#[serde(with = "MouseButtonProxy")] MouseButton),
+    /// a button on any connected gamepad
+    Pad(PadButton),
+}
+
+impl Binding {
+    pub fn key(key: KeyCode) -> Self {
+        Binding::Key { key, shift: false }
+    }
+
+    pub fn shift(key: KeyCode) -> Self {
+        Binding::Key { key, shift: true }
+    }
+
+    pub fn label(&self) -> String {
+        match self {
+            Binding::Key { key, shift: false } => format!("{:?}", key),
+            Binding::Key { key, shift: true } => format!("Shift+{:?}", key),
+            Binding::Mouse(button) => format!("Mouse {:?}", button),
+            Binding::Pad(button) => format!("Pad {:?}", button),
+        }
+    }
+
+    /// shift has to match exactly, so Shift+W and W don't trigger each other
+    fn shift_matches(shift: bool) -> bool {
+        shift == (is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift))
+    }
+
+    pub fn pressed(&self) -> bool {
+        match *self {
+            Binding::Key { key, shift } => is_key_pressed(key) && Self::shift_matches(shift),
+            Binding::Mouse(button) => is_mouse_button_pressed(button),
+            Binding::Pad(button) => PAD.with_borrow(|pad| pad.pressed.contains(&button)),
+        }
+    }
+
+    pub fn released(&self) -> bool {
+        match *self {
+            Binding::Key { key, shift } => is_key_released(key) && Self::shift_matches(shift),
+            Binding::Mouse(button) => is_mouse_button_released(button),
+            Binding::Pad(button) => PAD.with_borrow(|pad| pad.released.contains(&button)),
+        }
+    }
+
+    pub fn down(&self) -> bool {
+        match *self {
+            Binding::Key { key, shift } => is_key_down(key) && Self::shift_matches(shift),
+            Binding::Mouse(button) => is_mouse_button_down(button),
+            Binding::Pad(button) => PAD.with_borrow(|pad| pad.down.contains(&button)),
+        }
+    }
+}
+
+/// gamepad buttons by their place, South is A on an xbox pad and cross on a playstation one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftShoulder,
+    RightShoulder,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// gamepad buttons as seen this frame, kept next to macroquad's keyboard and mouse state
+#[derive(Debug, Default)]
+struct PadState {
+    down: HashSet<PadButton>,
+    pressed: HashSet<PadButton>,
+    released: HashSet<PadButton>,
+    /// None until the first update, Some(None) if there is no gamepad support
+    #[cfg(all(feature = "gamepad", not(target_arch = "wasm32")))]
+    gilrs: Option<Option<gilrs::Gilrs>>,
+}
+
+impl PadState {
+    /// forgets what was pressed and released last frame
+    fn next_frame(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+    }
+
+    fn press(&mut self, button: PadButton) {
+        if self.down.insert(button) {
+            self.pressed.insert(button);
+        }
+    }
+
+    fn release(&mut self, button: PadButton) {
+        if self.down.remove(&button) {
+            self.released.insert(button);
+        }
+    }
+
+    #[cfg(all(feature = "gamepad", not(target_arch = "wasm32")))]
+    fn poll(&mut self) {
+        use gilrs::{Button, EventType, Gilrs};
+        let gilrs = self.gilrs.get_or_insert_with(|| {
+            Gilrs::new()
+                .inspect_err(|e| eprintln!("no gamepad support: {}", e))
+                .ok()
+        });
+        let Some(gilrs) = gilrs else {
+            return;
+        };
+        let mut events = Vec::new();
+        while let Some(event) = gilrs.next_event() {
+            events.push(event.event);
+        }
+        let button = |button| match button {
+            Button::South => Some(PadButton::South),
+            Button::East => Some(PadButton::East),
+            Button::North => Some(PadButton::North),
+            Button::West => Some(PadButton::West),
+            Button::LeftTrigger => Some(PadButton::LeftShoulder),
+            Button::RightTrigger => Some(PadButton::RightShoulder),
+            Button::Select => Some(PadButton::Select),
+            Button::Start => Some(PadButton::Start),
+            Button::DPadUp => Some(PadButton::DPadUp),
+            Button::DPadDown => Some(PadButton::DPadDown),
+            Button::DPadLeft => Some(PadButton::DPadLeft),
+            Button::DPadRight => Some(PadButton::DPadRight),
+            _ => None,
+        };
+        for event in events {
+            match event {
+                EventType::ButtonPressed(b, _) => {
+                    if let Some(b) = button(b) {
+                        self.press(b);
+                    }
+                }
+                EventType::ButtonReleased(b, _) => {
+                    if let Some(b) = button(b) {
+                        self.release(b);
+                    }
+                }
+                // an unplugged pad doesn't send releases anymore
+                EventType::Disconnected => self.down.clear(),
+                _ => {}
+            }
+        }
+    }
+
+    /// without the `gamepad` feature pad bindings never trigger
+    #[cfg(not(all(feature = "gamepad", not(target_arch = "wasm32"))))]
+    fn poll(&mut self) {}
+}
+
+thread_local! {
+    static PAD: RefCell<PadState> = RefCell::default();
+}
+
+/// reads the gamepad events of this frame, has to run once per frame before any input is checked
+pub fn update_gamepads() {
+    PAD.with_borrow_mut(|pad| {
+        pad.next_frame();
+        pad.poll();
+    });
+}
+
+/// the first key, mouse or gamepad button pressed this frame, to bind it to something
+pub fn pressed_binding() -> Option<Binding> {
+    let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+    if let Some(key) = get_last_key_pressed()
+        .filter(|key| !matches!(key, KeyCode::LeftShift | KeyCode::RightShift))
+    {
+        return Some(Binding::Key { key, shift });
+    }
+    let pad = PAD.with_borrow(|pad| pad.pressed.iter().next().copied());
+    if let Some(button) = pad {
+        return Some(Binding::Pad(button));
+    }
+    [MouseButton::Left, MouseButton::Middle, MouseButton::Right]
+        .into_iter()
+        .find(|button| is_mouse_button_pressed(*button))
+        .map(Binding::Mouse)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Keybindings {
+    pub bindings: BTreeMap<Action, Vec<Binding>>,
+    /// set while typing into a text field, keys then only go to the ui
+    #[serde(skip)]
+    pub ignore_keys: bool,
+    /// just bound, doesn't count until it is let go
+    #[serde(skip)]
+    swallowed: Option<Binding>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            bindings: Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_bindings()))
+                .collect(),
+            ignore_keys: false,
+            swallowed: None,
+        }
+    }
+}
+
+impl Keybindings {
+    /// defaults overwritten by whatever the json sets
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut result = Self::default();
+        let overrides: Keybindings = serde_json::from_str(json)?;
+        result.bindings.extend(overrides.bindings);
+        Ok(result)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// defaults if there is no file, e.g. on the web
+    pub fn load(path: &str) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Self::from_json(&json),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_json())?;
+        Ok(())
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.bindings
+            .get(&action)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// bindings that currently count
+    fn active(&self, action: Action) -> impl Iterator<Item = &Binding> {
+        self.get(action)
+            .iter()
+            .filter(|b| !(self.ignore_keys && matches!(b, Binding::Key { .. })))
+            .filter(|b| self.swallowed != Some(**b))
+    }
+
+    /// `binding` triggers nothing until it is released,
+    /// so a click that gets bound doesn't also click once it is let go
+    pub fn swallow(&mut self, binding: Binding) {
+        self.swallowed = Some(binding);
+    }
+
+    /// has to run once per frame before any input is checked
+    pub fn update(&mut self) {
+        // the frame it is released in is still swallowed
+        if let Some(binding) = self.swallowed {
+            if !binding.down() && !binding.released() {
+                self.swallowed = None;
+            }
+        }
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.active(action).any(Binding::pressed)
+    }
+
+    pub fn released(&self, action: Action) -> bool {
+        self.active(action).any(Binding::released)
+    }
+
+    pub fn down(&self, action: Action) -> bool {
+        self.active(action).any(Binding::down)
+    }
+
+    /// adds `binding` to `action` unless it is already there
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    pub fn unbind(&mut self, action: Action, binding: Binding) {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            bindings.retain(|b| *b != binding);
+        }
+    }
+
+    /// other actions sharing a binding with `action`
+    pub fn conflicts(&self, action: Action) -> Vec<(Binding, Action)> {
+        let mut result = Vec::new();
+        for binding in self.get(action) {
+            for (other, bindings) in self.bindings.iter() {
+                if *other != action && bindings.contains(binding) {
+                    result.push((*binding, *other));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "MouseButton")]
+enum MouseButtonProxy {
+    Left,
+    Middle,
+    Right,
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "KeyCode")]
+enum KeyCodeProxy {
+    Space,
+    Apostrophe,
+    Comma,
+    Minus,
+    Period,
+    Slash,
+    Key0,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Semicolon,
+    Equal,
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    LeftBracket,
+    Backslash,
+    RightBracket,
+    GraveAccent,
+    World1,
+    World2,
+    Escape,
+    Enter,
+    Tab,
+    Backspace,
+    Insert,
+    Delete,
+    Right,
+    Left,
+    Down,
+    Up,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    CapsLock,
+    ScrollLock,
+    NumLock,
+    PrintScreen,
+    Pause,
+    F1,
+    F2,
+    F3,
+    F4,
+    F5,
+    F6,
+    F7,
+    F8,
+    F9,
+    F10,
+    F11,
+    F12,
+    F13,
+    F14,
+    F15,
+    F16,
+    F17,
+    F18,
+    F19,
+    F20,
+    F21,
+    F22,
+    F23,
+    F24,
+    F25,
+    Kp0,
+    Kp1,
+    Kp2,
+    Kp3,
+    Kp4,
+    Kp5,
+    Kp6,
+    Kp7,
+    Kp8,
+    Kp9,
+    KpDecimal,
+    KpDivide,
+    KpMultiply,
+    KpSubtract,
+    KpAdd,
+    KpEnter,
+    KpEqual,
+    LeftShift,
+    LeftControl,
+    LeftAlt,
+    LeftSuper,
+    RightShift,
+    RightControl,
+    RightAlt,
+    RightSuper,
+    Menu,
+    Back,
+    Unknown,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_dont_conflict() {
+        let bindings = Keybindings::default();
+        for action in Action::ALL {
+            assert!(!bindings.get(action).is_empty(), "{:?}", action);
+            assert_eq!(Vec::<(Binding, Action)>::new(), bindings.conflicts(action));
+        }
+    }
+
+    #[test]
+    fn config_overrides_defaults() {
+        let json = r#"{"bindings": {
+            "EndTurn": [{"Key": {"key": "Enter"}}],
+            "ContextMenu": [{"Key": {"key": "C", "shift": true}}, {"Mouse": "Right"}]
+        }}"#;
+        let bindings = Keybindings::from_json(json).unwrap();
+        assert_eq!(
+            &[Binding::key(KeyCode::Enter)],
+            bindings.get(Action::EndTurn)
+        );
+        assert_eq!(
+            &[
+                Binding::shift(KeyCode::C),
+                Binding::Mouse(MouseButton::Right)
+            ],
+            bindings.get(Action::ContextMenu)
+        );
+        // untouched actions keep their defaults
+        assert_eq!(
+            &[
+                Binding::key(KeyCode::Q),
+                Binding::Pad(PadButton::RightShoulder)
+            ],
+            bindings.get(Action::NextUnit)
+        );
+        assert_eq!(
+            vec![(Binding::key(KeyCode::Enter), Action::Confirm)],
+            bindings.conflicts(Action::EndTurn)
+        );
+
+        let round_trip = Keybindings::from_json(&bindings.to_json()).unwrap();
+        assert_eq!(bindings, round_trip);
+        assert!(Keybindings::from_json(r#"{"bindings": {"Fly": []}}"#).is_err());
+    }
+
+    #[test]
+    fn pad_buttons_are_pressed_for_one_frame() {
+        let mut pad = PadState::default();
+        pad.press(PadButton::South);
+        assert!(pad.pressed.contains(&PadButton::South));
+        pad.next_frame();
+        // held, but not pressed again
+        pad.press(PadButton::South);
+        assert!(pad.pressed.is_empty());
+        assert!(pad.down.contains(&PadButton::South));
+        pad.release(PadButton::South);
+        assert!(pad.released.contains(&PadButton::South));
+        assert!(pad.down.is_empty());
+
+        let json = r#"{"bindings": {"Confirm": [{"Pad": "West"}]}}"#;
+        let bindings = Keybindings::from_json(json).unwrap();
+        assert_eq!(
+            &[Binding::Pad(PadButton::West)],
+            bindings.get(Action::Confirm)
+        );
+    }
+}
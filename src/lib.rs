@@ -0,0 +1,39 @@
+//! the rules of comfy wars without a window
+//! the game binary builds on top of this, other code can drive matches through `env`
+
+pub mod ai;
+pub mod ascii_map;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bot;
+pub mod comfy_compat;
+pub mod data;
+pub mod dijkstra;
+pub mod egui_macroquad;
+pub mod env;
+pub mod game;
+pub mod grids;
+#[allow(unused)]
+mod loading;
+pub mod reach;
+pub mod sim;
+pub mod topology;
+pub mod util;
+pub mod vision;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use anyhow::Result;
+use comfy_compat::*;
+use data::*;
+use dijkstra::*;
+use game::*;
+use grids::*;
+use itertools::Itertools;
+use loading::*;
+use macroquad::prelude::*;
+use nanoserde::*;
+use reach::Reachability;
+use serde::{Deserialize, Serialize};
+use slotmap::SlotMap;
+use topology::Topology;
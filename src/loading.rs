@@ -2,7 +2,6 @@
 /// release and wasm builds include them in the binary
 /// while other builds just load them from disk
 /// it may be possible to turn this into a fancy hotreloading wrapper
-
 // debug build for desktop
 #[cfg(all(not(target_arch = "wasm32"), debug_assertions))]
 macro_rules! kf_include_bytes {
@@ -1,20 +1,32 @@
 #![allow(unused)]
-mod data;
 #[macro_use]
 mod debug;
+mod autotile;
 mod camera;
-mod comfy_compat;
-mod dijkstra;
-mod egui_macroquad;
-mod game;
-mod grids;
+mod draw;
+mod editor;
+mod keybindings;
 mod loading;
-mod util;
+mod mapgen;
+mod render;
+mod touch;
+#[cfg(not(target_arch = "wasm32"))]
+mod tournament;
+
+#[cfg(not(target_arch = "wasm32"))]
+use comfy_wars::bot;
+use comfy_wars::{
+    ai, ascii_map, comfy_compat, data, dijkstra, egui_macroquad, env, game, grids, reach, sim,
+    topology, util, vision,
+};
 
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::Arc;
 
+use ai::*;
 use anyhow::Result;
 use camera::CameraWrapper;
 use comfy_compat::*;
@@ -22,17 +34,24 @@ use cosync::{Cosync, CosyncInput, CosyncQueueHandle};
 use data::*;
 use debug::*;
 use dijkstra::*;
+use draw::{DrawBackend, DrawCommand, DrawKind, MacroquadBackend, SpriteBatch};
+use editor::{apply_brush, resize_map, Brush};
 use egui::epaint;
 use game::*;
 use grids::*;
 use inline_tweak::tweak;
 use itertools::Itertools;
+use keybindings::{Action, Binding, Keybindings, KEYBINDINGS_PATH};
 use loading::*;
 use macroquad::prelude::*;
+use mapgen::MapGenConfig;
 use nanoserde::*;
+use reach::{ReachCache, Reachability};
 use serde::{Deserialize, Serialize};
-use slotmap::{new_key_type, SlotMap};
-use util::Vec2f;
+use slotmap::SlotMap;
+use topology::Topology;
+use touch::TouchInput;
+use util::{option_ivec2_proxy, IVec2Proxy, Vec2f};
 
 fn window_conf() -> Conf {
     Conf {
@@ -44,10 +63,100 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
+fn main() {
+    let args = std::env::args().skip(1).collect_vec();
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.first().map(String::as_str) == Some("tournament") {
+        if let Err(e) = tournament::tournament_main(&args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.first().map(String::as_str) == Some("render") {
+        if let Err(e) = render::render_main(&args[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let args = GameArgs::parse(&args).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    macroquad::Window::from_config(
+        window_conf(),
+        game_main(args.bot_command, args.map_path, args.seed),
+    );
+}
+
+const USAGE: &str = "usage: comfy-wars [--bot <command>] [--map <file> | --seed <n>]";
+
+/// command line of the game itself, tournament and render parse their own
+#[derive(Debug, Default, PartialEq)]
+struct GameArgs {
+    /// delegate the enemy team to an external program
+    bot_command: Option<String>,
+    /// a map saved by the editor instead of the built-in one
+    map_path: Option<String>,
+    /// a generated skirmish map instead of the built-in one
+    seed: Option<u64>,
+}
+
+impl GameArgs {
+    fn parse(args: &[String]) -> Result<Self> {
+        let arg = |name: &str| {
+            args.iter()
+                .position(|arg| arg == name)
+                .and_then(|i| args.get(i + 1))
+                .cloned()
+        };
+        let seed = arg("--seed")
+            .map(|seed| {
+                seed.parse::<u64>()
+                    .map_err(|e| anyhow::anyhow!("bad seed '{}': {}\n{}", seed, e, USAGE))
+            })
+            .transpose()?;
+        let result = Self {
+            bot_command: arg("--bot"),
+            map_path: arg("--map"),
+            seed,
+        };
+        if result.map_path.is_some() && result.seed.is_some() {
+            anyhow::bail!(
+                "--map and --seed both pick the map, use only one\n{}",
+                USAGE
+            );
+        }
+        Ok(result)
+    }
+}
+
+// there are no external bots on wasm
+#[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
+async fn game_main(bot_command: Option<String>, map_path: Option<String>, seed: Option<u64>) {
     let game_wrapper = &mut GameWrapper::new();
     setup(game_wrapper).await.unwrap();
+    if let Some(path) = map_path {
+        match GameMap::load(&path) {
+            Ok(map) => load_map(&mut game_wrapper.game_state, map),
+            Err(e) => eprintln!("could not load map '{}': {}", path, e),
+        }
+    }
+    if let Some(seed) = seed {
+        let s = &mut game_wrapper.game_state;
+        match mapgen::generate(seed, &MapGenConfig::default(), &s.entity_defs) {
+            Ok(generated) => load_map(s, generated.map),
+            Err(e) => eprintln!("could not generate a map from seed {}: {}", seed, e),
+        }
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(command) = bot_command {
+        match bot::ExternalBot::spawn(&command, bot::DEFAULT_BOT_TIMEOUT) {
+            Ok(bot) => game_wrapper.game_state.enemy.bot = Some(bot),
+            Err(e) => eprintln!("could not start bot '{}': {}", command, e),
+        }
+    }
     loop {
         clear_background(BLACK);
 
@@ -80,6 +189,12 @@ pub struct GameWrapper {
     game_state: GameState,
 }
 
+impl Default for GameWrapper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GameWrapper {
     pub fn new() -> Self {
         let cosync = Cosync::new();
@@ -91,18 +206,36 @@ impl GameWrapper {
     }
 }
 
+/// where a sprite is in the tilemap
 #[derive(Clone)]
 struct Sprite {
-    params: DrawTextureParams,
-    texture: Texture2D,
+    src: Rect,
 }
 
 struct SpriteWithPos {
-    params: DrawTextureParams,
-    texture: Texture2D,
+    src: Rect,
     pos: Vec2,
 }
 
+/// ground and terrain don't change while playing, so their vertices are built once on the cpu
+/// macroquad still uploads them to the gpu every frame, only building them is saved
+struct TileLayers {
+    /// what the vertices depend on besides the tiles
+    topology: Topology,
+    texture_size: Vec2,
+    ground: Vec<TileChunk>,
+    terrain: Vec<TileChunk>,
+}
+
+/// tiles are culled in square chunks of this many tiles per side
+const CHUNK_TILES: i32 = 16;
+
+/// the tiles of a chunk, drawn if `bounds` are on screen
+struct TileChunk {
+    bounds: Rect,
+    batch: Arc<SpriteBatch>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GameState {
     #[serde(skip, default = "null_object_queue_handle")]
@@ -112,70 +245,70 @@ pub struct GameState {
     ui: UIState,
     #[serde(skip)]
     sprites: HashMap<String, Sprite>,
+    /// every sprite is a part of it, None until loaded
+    #[serde(skip)]
+    tilemap: Option<Texture2D>,
     #[serde(skip)]
     ground_sprites: Vec<SpriteWithPos>,
     #[serde(skip)]
     terrain_sprites: Vec<SpriteWithPos>,
+    /// the tile sprites as vertices, None when they have to be rebuilt
+    #[serde(skip)]
+    tile_layers: Option<TileLayers>,
+    /// the world rect on screen, only what overlaps it is drawn, None draws everything
+    #[serde(skip)]
+    view: Option<Rect>,
     grids: Grids,
     entities: SlotMap<ActorKey, Actor>,
     phase: GamePhase,
     #[serde(skip)]
     camera: CameraWrapper,
-}
-
-struct DrawCommand {
-    z_level: i32,
-    command: Box<dyn FnOnce() -> ()>,
+    /// decides for the enemy team
+    #[serde(skip)]
+    enemy: Controller,
+    #[serde(skip)]
+    reach_cache: ReachCache,
+    #[serde(skip)]
+    touch: TouchInput,
+    #[serde(skip)]
+    bindings: Keybindings,
+    /// unit types by name
+    #[serde(skip)]
+    entity_defs: HashMap<String, EntityDef>,
 }
 
 impl GameState {
+    /// world position to where it's drawn, see `Topology::display_pos`
+    fn display_pos(&self, pos: impl Into<Vec2f>) -> Vec2f {
+        let pos: Vec2 = pos.into().into();
+        self.grids.topology.display_pos(pos, GRIDSIZE as f32).into()
+    }
+
     fn draw_sprite(&self, name: &str, dp: impl Into<Vec2f>, z_level: i32, color: Color) {
-        let dp = dp.into();
-        let sprite = (&self.sprites[name]).clone();
-        let command = move || {
-            my_draw_texture(
-                &sprite.texture,
-                dp.x,
-                dp.y,
-                color,
-                sprite.params.source.unwrap(),
-            );
+        let kind = DrawKind::Sprite {
+            name: Some(name.to_string()),
+            src: self.sprites[name].src,
         };
-        self.draw_buffer.borrow_mut().push(DrawCommand {
-            z_level,
-            command: Box::new(command),
-        });
+        self.push_draw(dp, z_level, color, kind);
+    }
+
+    fn draw_rect(&self, dp: impl Into<Vec2f>, w: f32, h: f32, z_level: i32, color: Color) {
+        self.push_draw(dp, z_level, color, DrawKind::Rectangle { w, h });
     }
 
-    fn draw_texture(
+    fn draw_line(
         &self,
-        texture: Texture2D,
-        dp: impl Into<Vec2f>,
+        from: impl Into<Vec2f>,
+        to: impl Into<Vec2f>,
+        thickness: f32,
         z_level: i32,
         color: Color,
-        params: DrawTextureParams,
     ) {
-        let dp = dp.into();
-        let command = move || {
-            my_draw_texture(&texture, dp.x, dp.y, color, params.source.unwrap());
-        };
-        self.draw_buffer.borrow_mut().push(DrawCommand {
-            z_level,
-            command: Box::new(command),
-        });
-    }
-
-    fn draw_rect(&self, dp: impl Into<Vec2f>, w: f32, h: f32, z_level: i32, color: Color) {
-        let dp = dp.into();
-        let command = move || {
-            draw_rectangle(dp.x, dp.y, GRIDSIZE as f32, GRIDSIZE as f32, color);
-        };
-        self.draw_buffer.borrow_mut().push(DrawCommand {
-            z_level,
-            command: Box::new(command),
-        });
+        let to = self.display_pos(to).into();
+        self.push_draw(from, z_level, color, DrawKind::Line { to, thickness });
     }
 
+    /// `dp` is where the text is drawn, it is not adjusted to the topology
     fn draw_text(
         &self,
         text: impl Into<String>,
@@ -183,30 +316,46 @@ impl GameState {
         z_level: i32,
         params: TextParams<'static>,
     ) {
-        let dp = dp.into();
-        let text = text.into();
-        let command = move || {
-            draw_text_ex(&text, dp.x, dp.y, params);
-        };
         self.draw_buffer.borrow_mut().push(DrawCommand {
             z_level,
-            command: Box::new(command),
+            pos: dp.into().into(),
+            color: params.color,
+            kind: DrawKind::Text {
+                text: text.into(),
+                font_size: params.font_size,
+                font_scale: params.font_scale,
+            },
         });
     }
 
-    fn flush_draw_buffer(&mut self) {
+    fn push_draw(&self, dp: impl Into<Vec2f>, z_level: i32, color: Color, kind: DrawKind) {
+        self.draw_buffer.borrow_mut().push(DrawCommand {
+            z_level,
+            pos: self.display_pos(dp).into(),
+            color,
+            kind,
+        });
+    }
+
+    /// whether a tile sized sprite at world position `pos` is on screen
+    fn is_visible(&self, pos: impl Into<Vec2f>) -> bool {
+        let pos: Vec2 = self.display_pos(pos).into();
+        let size = GRIDSIZE as f32;
+        self.view
+            .is_none_or(|view| view.overlaps(&Rect::new(pos.x, pos.y, size, size)))
+    }
+
+    /// executes everything queued this frame, lower z first
+    fn flush_draw_buffer(&mut self, backend: &mut dyn DrawBackend) {
         let buffer = &mut self.draw_buffer.borrow_mut();
         buffer.sort_by_key(|it| it.z_level);
-        for draw in buffer.drain(..) {
-            (draw.command)();
+        for command in buffer.drain(..) {
+            backend.execute(&command);
         }
+        backend.finish();
     }
 }
 
-new_key_type! {
-    struct ActorKey;
-}
-
 fn null_object_queue_handle() -> CosyncQueueHandle<GameState> {
     let cosync = Cosync::new();
     cosync.create_queue_handle()
@@ -218,13 +367,21 @@ impl GameState {
             draw_buffer: Default::default(),
             ui: Default::default(),
             sprites: Default::default(),
+            tilemap: None,
             grids: Default::default(),
             co,
             entities: Default::default(),
             phase: Default::default(),
             ground_sprites: Default::default(),
             terrain_sprites: Default::default(),
+            tile_layers: None,
+            view: None,
             camera: Default::default(),
+            enemy: Default::default(),
+            reach_cache: Default::default(),
+            touch: Default::default(),
+            bindings: Default::default(),
+            entity_defs: Default::default(),
         }
     }
 }
@@ -234,6 +391,7 @@ enum GamePhase {
     #[default]
     PlayerPhase,
     EnemyPhase,
+    Editor,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -242,11 +400,36 @@ struct UIState {
     right_click_menu_pos: Option<Vec2>,
     cursor_pos: Option<Vec2f>,
     last_mouse_pos: Vec2f,
+    /// tile under the cursor, follows the mouse or the keyboard, whichever was used last
+    #[serde(with = "IVec2Proxy")]
+    grid_cursor: IVec2,
     draw_dijkstra_map: bool,
     draw_ai_map: bool,
+    draw_fov: bool,
+    /// tiles any enemy could attack next turn
+    draw_threat: bool,
+    /// enemy whose move and attack range is shown
+    inspected_enemy: Option<ActorKey>,
     selected_entity: Option<ActorKey>,
     move_state: MoveState,
+    /// where the selected unit stood before moving, to undo the move
+    #[serde(with = "option_ivec2_proxy")]
+    move_origin: Option<IVec2>,
+    /// highlighted button of the confirm menu for keyboard use
+    menu_index: usize,
     chosen_enemy: Option<usize>,
+    show_keybindings: bool,
+    brush: Brush,
+    /// width and height for resizing in the editor
+    editor_size: (i32, i32),
+    /// where the editor saves to and loads from
+    map_path: String,
+    /// settings for generating a skirmish map in the editor
+    map_gen: MapGenConfig,
+    map_seed: u64,
+    /// action waiting for a key or mouse button to be bound to it
+    #[serde(skip)]
+    rebinding: Option<Action>,
 }
 
 #[derive(Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -260,174 +443,111 @@ enum MoveState {
     Attacking, // Animation
 }
 
-/// all grids in here have the same dimensions
-/// bigger x is right
-/// bigger y is down (reverse of what comfy uses atm)
-#[derive(Debug, Serialize, Deserialize)]
-struct Grids {
-    ground: Grid<GroundType>,
-    terrain: Grid<TerrainType>,
-}
-
-impl Default for Grids {
-    fn default() -> Self {
-        Self {
-            ground: Grid::new(0, 0, Default::default()),
-            terrain: Grid::new(0, 0, Default::default()),
-        }
-    }
-}
-
-const GRIDSIZE: i32 = 16;
-
 async fn setup(s: &mut GameWrapper) -> Result<()> {
     let s = &mut s.game_state;
+    // touches are handled as gestures, not as a left mouse button
+    simulate_mouse_with_touch(false);
+    s.bindings = Keybindings::load(KEYBINDINGS_PATH).unwrap_or_else(|e| {
+        eprintln!(
+            "could not load {}, using default keys: {}",
+            KEYBINDINGS_PATH, e
+        );
+        Keybindings::default()
+    });
     // load tiles
     let ldtk: LDTK = DeJson::deserialize_json(kf_include_str!("/assets/comfy_wars.ldtk")).unwrap();
-    {
-        let level = &ldtk.levels[0];
-        let (w, h) = (level.pixel_width / GRIDSIZE, level.pixel_height / GRIDSIZE);
-        s.grids.ground = Grid::new(w, h, Default::default());
-    }
+    let level = &ldtk.levels[0];
+    s.grids = grids_from_level(level)?;
 
     let texture = load_texture("assets/tilemap/tilemap_packed.png")
         .await
         .expect("Tilemap not found");
     texture.set_filter(FilterMode::Nearest);
 
-    // load sprites
-    let sprites_str = kf_include_str!("/assets/sprites.json");
-    let sprite_datas: HashMap<String, SpriteData> = DeJson::deserialize_json(sprites_str).unwrap();
-    s.sprites = HashMap::new();
-    for (name, data) in sprite_datas.into_iter() {
-        let source_rect = Rect {
-            x: data.x as _,
-            y: data.y as _,
-            w: GRIDSIZE as _,
-            h: GRIDSIZE as _,
-        };
-        let sprite = Sprite {
-            params: DrawTextureParams {
-                source: Some(source_rect),
-                ..Default::default()
-            },
-            texture: texture.clone(),
-        };
-        s.sprites.insert(name, sprite);
-    }
-
-    for layer in ldtk
-        .levels
-        .iter()
-        .flat_map(|level| level.layers.iter())
-        .filter(|layer| layer.id == "groundgrid")
-    {
-        s.grids.ground = grid_from_layer(layer, |i| match i {
-            1 => GroundType::Ground,
-            2 => GroundType::Water,
-            _ => panic!("unsupported ground type {}", i),
-        });
-
-        s.ground_sprites = layer
+    let tile_sprites = |layer: &Layer| {
+        layer
             .auto_tiles
             .iter()
-            .map(|tile| {
-                let source_rect = Rect {
-                    x: tile.src[0] as _,
-                    y: tile.src[1] as _,
-                    w: GRIDSIZE as _,
-                    h: GRIDSIZE as _,
-                };
-                SpriteWithPos {
-                    params: DrawTextureParams {
-                        source: Some(source_rect),
-                        ..Default::default()
-                    },
-                    texture: texture.clone(),
-                    pos: vec2(tile.px[0], tile.px[1]),
-                }
+            .map(|tile| SpriteWithPos {
+                src: tile_rect(tile.src[0], tile.src[1]),
+                pos: vec2(tile.px[0], tile.px[1]),
             })
-            .collect_vec();
+            .collect_vec()
+    };
+    for layer in level.layers.iter() {
+        match layer.id.as_str() {
+            "groundgrid" => s.ground_sprites = tile_sprites(layer),
+            "infrastructuregrid" => s.terrain_sprites = tile_sprites(layer),
+            _ => {}
+        }
     }
 
-    for layer in ldtk
-        .levels
-        .iter()
-        .flat_map(|level| level.layers.iter())
-        .filter(|layer| layer.id == "infrastructuregrid")
-    {
-        s.grids.terrain = grid_from_layer(layer, |i| match i {
-            0 => TerrainType::None,
-            1 | 2 | 3 | 4 => TerrainType::Street,
-            5 => TerrainType::Forest,
-            _ => panic!("unsupported terrain type {}", i),
-        });
-        s.terrain_sprites = layer
-            .auto_tiles
-            .iter()
-            .map(|tile| {
-                let source_rect = Rect {
-                    x: tile.src[0] as _,
-                    y: tile.src[1] as _,
-                    w: GRIDSIZE as _,
-                    h: GRIDSIZE as _,
-                };
-                SpriteWithPos {
-                    params: DrawTextureParams {
-                        source: Some(source_rect),
-                        ..Default::default()
-                    },
-                    texture: texture.clone(),
-                    pos: vec2(tile.px[0], tile.px[1]),
-                }
-            })
-            .collect_vec();
+    // load entity definitions and sprites
+    let entity_defs = data::entity_defs().unwrap();
+    s.sprites = load_sprites(&entity_defs);
+    s.tilemap = Some(texture);
+
+    // load entities on map
+    for actor in actors_from_level(level, &entity_defs)? {
+        s.entities.insert(actor);
     }
+    s.entity_defs = entity_defs;
 
-    // load entity definitions
-    let entity_defs: HashMap<String, EntityDef> =
-        DeJson::deserialize_json(kf_include_str!("/assets/entities_def.json")).unwrap();
+    Ok(())
+}
 
-    // load entities on map
-    let em = kf_include_str!("/assets/entities_map.json");
-    let map_entities: Vec<EntityOnMap> = DeJson::deserialize_json(em).unwrap();
-
-    for (name, def) in &entity_defs {
-        let source_rect = Rect {
-            x: def.sprite.x as _,
-            y: def.sprite.y as _,
-            w: GRIDSIZE as _,
-            h: GRIDSIZE as _,
-        };
-        let params = DrawTextureParams {
-            source: Some(source_rect),
-            ..Default::default()
-        };
-        s.sprites.insert(
-            name.clone(),
-            Sprite {
-                params,
-                texture: texture.clone(),
-            },
-        );
+/// a tile sized rect of the tilemap
+fn tile_rect(x: i32, y: i32) -> Rect {
+    Rect {
+        x: x as _,
+        y: y as _,
+        w: GRIDSIZE as _,
+        h: GRIDSIZE as _,
     }
+}
 
-    for me in map_entities {
-        let name = &me.def;
-        let def = &entity_defs[&me.def];
-        s.entities.insert(Actor {
-            pos: me.pos.into(),
-            draw_pos: vec2((me.pos[0] * GRIDSIZE) as f32, (me.pos[1] * GRIDSIZE) as f32),
-            sprite_coords: ivec2(def.sprite.x, def.sprite.y),
-            sprite_name: name.clone(),
-            team: def.team,
-            unit_type: def.unit_type,
-            hp: HP_MAX,
-            has_moved: false,
-        });
+/// the sprites from sprites.json and one for every unit type
+fn load_sprites(entity_defs: &HashMap<String, EntityDef>) -> HashMap<String, Sprite> {
+    let sprites_str = kf_include_str!("/assets/sprites.json");
+    let sprite_datas: HashMap<String, SpriteData> = DeJson::deserialize_json(sprites_str).unwrap();
+    let units = entity_defs.iter().map(|(name, def)| (name, &def.sprite));
+    sprite_datas
+        .iter()
+        .chain(units)
+        .map(|(name, data)| {
+            let src = tile_rect(data.x, data.y);
+            (name.clone(), Sprite { src })
+        })
+        .collect()
+}
+
+/// replaces the current map, e.g. one saved by the editor
+fn load_map(s: &mut GameState, map: GameMap) {
+    s.grids = map.grids;
+    s.entities.clear();
+    s.reach_cache.invalidate();
+    for actor in map.actors {
+        s.entities.insert(actor);
     }
+    s.ui.selected_entity = None;
+    s.ui.inspected_enemy = None;
+    rebuild_tile_sprites(s);
+}
 
-    Ok(())
+/// derives the tiles from the grids, since only maps from ldtk come with their own
+fn rebuild_tile_sprites(s: &mut GameState) {
+    let sprites = |tiles: Vec<autotile::AutoTile>| {
+        tiles
+            .into_iter()
+            .map(|tile| SpriteWithPos {
+                src: tile_rect(tile.src.x, tile.src.y),
+                pos: game_to_world(tile.pos),
+            })
+            .collect()
+    };
+    s.ground_sprites = sprites(autotile::ground_tiles(&s.grids.ground));
+    s.terrain_sprites = sprites(autotile::terrain_tiles(&s.grids.terrain));
+    s.tile_layers = None;
 }
 
 fn update(s: &mut GameWrapper) {
@@ -443,19 +563,38 @@ fn update(s: &mut GameWrapper) {
     };
     egui().set_visuals(visuals);
 
-    if is_key_pressed(KeyCode::F5) {
+    keybindings::update_gamepads();
+    s.bindings.update();
+    // keys pressed for rebinding shouldn't do anything else
+    let rebinding = s.ui.rebinding.is_some();
+    s.bindings.ignore_keys = egui().wants_keyboard_input() || rebinding;
+    if s.bindings.pressed(Action::SaveGame) && !rebinding {
         println!("Saving game.");
     }
-    if is_key_pressed(KeyCode::F9) {
+    if s.bindings.pressed(Action::LoadGame) && !rebinding {
         println!("Loading game.");
     }
 
     s.camera.process();
+    s.view = Some(s.camera.visible_rect());
     draw_tiles(s);
-    if s.phase == GamePhase::PlayerPhase {
-        handle_input(s);
+    if s.bindings.pressed(Action::ToggleEditor) && !rebinding {
+        toggle_editor(s);
+    }
+    match s.phase {
+        GamePhase::PlayerPhase if !rebinding => handle_input(s),
+        GamePhase::Editor if !rebinding => handle_editor_input(s),
+        _ => {}
     }
     handle_debug_input(s);
+    if s.bindings.pressed(Action::ShowKeybindings) && !rebinding {
+        s.ui.show_keybindings = !s.ui.show_keybindings;
+    }
+    if s.ui.show_keybindings {
+        draw_keybindings(s);
+    } else {
+        s.ui.rebinding = None;
+    }
     draw_actors(s);
 
     // TODO remove this indirection
@@ -464,35 +603,68 @@ fn update(s: &mut GameWrapper) {
     }
 
     cw_debug!("Draw calls buffered: {}", s.draw_buffer.borrow().len());
-    s.flush_draw_buffer();
+    if let Some(tilemap) = s.tilemap.clone() {
+        s.flush_draw_buffer(&mut MacroquadBackend::new(&tilemap));
+    }
 }
 
 fn draw_tiles(s: &mut GameState) {
-    for sprite in s.ground_sprites.iter() {
-        let pos: Vec2f = sprite.pos.into();
-        s.draw_texture(
-            sprite.texture.clone(),
-            pos,
-            Z_GROUND,
-            WHITE,
-            sprite.params.clone(),
-        )
-    }
-    for sprite in s.terrain_sprites.iter() {
-        let pos: Vec2f = sprite.pos.into();
-        s.draw_texture(
-            sprite.texture.clone(),
-            pos,
-            Z_TERRAIN,
-            WHITE,
-            sprite.params.clone(),
-        )
+    // the uvs only matter once there is a tilemap to draw with
+    let texture_size = s.tilemap.as_ref().map_or(Vec2::ONE, Texture2D::size);
+    let topology = s.grids.topology;
+    let outdated =
+        |layers: &TileLayers| layers.topology != topology || layers.texture_size != texture_size;
+    if s.tile_layers.as_ref().is_none_or(outdated) {
+        let bake = |sprites: &[SpriteWithPos]| {
+            let chunk_size = (CHUNK_TILES * GRIDSIZE) as f32;
+            let mut chunks = BTreeMap::<(i32, i32), (Rect, SpriteBatch)>::new();
+            for sprite in sprites {
+                let chunk = (sprite.pos / chunk_size).floor().as_ivec2();
+                let pos: Vec2 = s.display_pos(sprite.pos).into();
+                let rect = Rect::new(pos.x, pos.y, sprite.src.w, sprite.src.h);
+                let (bounds, batch) = chunks
+                    .entry((chunk.y, chunk.x))
+                    .or_insert((rect, SpriteBatch::default()));
+                *bounds = bounds.combine_with(rect);
+                batch.push(pos, sprite.src, WHITE, texture_size);
+            }
+            chunks
+                .into_values()
+                .map(|(bounds, batch)| TileChunk {
+                    bounds,
+                    batch: Arc::new(batch),
+                })
+                .collect_vec()
+        };
+        s.tile_layers = Some(TileLayers {
+            topology,
+            texture_size,
+            ground: bake(&s.ground_sprites),
+            terrain: bake(&s.terrain_sprites),
+        });
+    }
+    let layers = s.tile_layers.as_ref().unwrap();
+    for (chunks, z_level) in [(&layers.ground, Z_GROUND), (&layers.terrain, Z_TERRAIN)] {
+        let visible = chunks
+            .iter()
+            .filter(|chunk| s.view.is_none_or(|view| view.overlaps(&chunk.bounds)));
+        for chunk in visible {
+            s.draw_buffer.borrow_mut().push(DrawCommand {
+                z_level,
+                pos: Vec2::ZERO,
+                color: WHITE,
+                kind: DrawKind::Batch(chunk.batch.clone()),
+            });
+        }
     }
 }
 
 fn draw_actors(s: &mut GameState) {
     // draw actors
     for (_index, actor) in s.entities.iter() {
+        if !s.is_visible(actor.draw_pos) {
+            continue;
+        }
         let color = if actor.has_moved { GRAY } else { WHITE };
         s.draw_sprite(&actor.sprite_name, actor.draw_pos, Z_UNIT_HP, color);
 
@@ -518,61 +690,80 @@ fn draw_actors(s: &mut GameState) {
 /// relevant for the actual game
 /// also does drawing in immediate mode
 fn handle_input(s: &mut GameState) {
-    if is_mouse_button_down(MouseButton::Middle) {
-        s.camera.mouse_delta(s.ui.last_mouse_pos, mouse_position());
-    }
-
-    s.ui.last_mouse_pos = mouse_position().into();
-    match mouse_wheel() {
-        (_x, y) => {
-            if y != 0. {
-                if y > 0. {
-                    s.camera.zoom(1);
-                }
-                if y < 0. {
-                    s.camera.zoom(-1);
-                }
-            }
-        }
+    let gestures = handle_pointer_input(s);
+    // taps on egui windows are handled by egui
+    let tap = gestures
+        .tap
+        .filter(|_| !egui().is_pointer_over_area())
+        .map(|pos| screen_game_grid(s, pos));
+    // the first tap on a tile moves the cursor there, the second one confirms
+    let second_tap = tap.is_some() && tap == Some(s.ui.grid_cursor);
+    if let Some(pos) = tap {
+        s.ui.grid_cursor = pos;
     }
 
-    if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
-        if is_key_pressed(KeyCode::W) {
-            s.camera.move_camera((0., -32.));
-        }
-        if is_key_pressed(KeyCode::S) {
-            s.camera.move_camera((0., 32.));
+    let mut confirm = s.bindings.pressed(Action::Confirm) || second_tap;
+    if s.ui.move_state == MoveState::None {
+        if let Some(step) = cursor_step(&s.bindings) {
+            move_cursor(s, s.ui.grid_cursor + step);
         }
-        if is_key_pressed(KeyCode::A) {
-            s.camera.move_camera((-32., 0.));
+        if s.bindings.pressed(Action::NextUnit) {
+            select_next_unit(s);
         }
-        if is_key_pressed(KeyCode::D) {
-            s.camera.move_camera((32., 0.));
+        if s.bindings.pressed(Action::Cancel) {
+            s.ui.selected_entity = None;
+            s.ui.inspected_enemy = None;
         }
     }
 
-    if is_mouse_button_released(MouseButton::Right) {
-        s.ui.right_click_menu_pos = Some(s.camera.mouse_world());
+    if let Some(pos) = context_menu_requested(s, &gestures) {
+        s.ui.right_click_menu_pos = Some(pos);
     }
-    if is_mouse_button_released(MouseButton::Left) && s.ui.move_state == MoveState::None {
+    let keyboard_select = confirm && s.ui.selected_entity.is_none();
+    // tapping a tile the selected unit can't move to works like a click
+    let tap_select = tap.is_some()
+        && s.ui
+            .selected_entity
+            .is_none_or(|e| path_to(&s.grids, &s.entities, e, s.ui.grid_cursor).is_empty());
+    let click = s.bindings.released(Action::Select) && !egui().is_pointer_over_area();
+    if (click || keyboard_select || tap_select) && s.ui.move_state == MoveState::None {
+        // selecting a unit doesn't move it right away
+        confirm = false;
         s.ui.right_click_menu_pos = None;
-        let pos = grid_world_pos(s.camera.mouse_world());
+        let pos = game_to_world(s.ui.grid_cursor);
         s.ui.selected_entity = None;
+        let clicked_enemy = actor_at_pos(&s.entities, s.ui.grid_cursor)
+            .filter(|enemy| s.entities[*enemy].team == ENEMY_TEAM);
+        // clicking the same enemy again hides its range
+        s.ui.inspected_enemy = clicked_enemy.filter(|enemy| s.ui.inspected_enemy != Some(*enemy));
 
         for (key, actor) in s.entities.iter() {
             // I am scared of floats
             if pos.abs_diff_eq(actor.draw_pos, 0.01)
                 && actor.team == PLAYER_TEAM
-                && actor.has_moved == false
+                && !actor.has_moved
             {
                 s.ui.selected_entity = Some(key);
             }
         }
     }
 
-    if is_key_pressed(KeyCode::End) {
-        s.phase = GamePhase::EnemyPhase;
-        s.co.queue(enemy_phase);
+    if s.bindings.pressed(Action::ToggleThreat) {
+        s.ui.draw_threat = !s.ui.draw_threat;
+    }
+    if s.ui.draw_threat {
+        draw_threat_range(s);
+    }
+    // may have died in the meantime
+    s.ui.inspected_enemy =
+        s.ui.inspected_enemy
+            .filter(|enemy| s.entities.contains_key(*enemy));
+    if let Some(enemy) = s.ui.inspected_enemy {
+        draw_enemy_range(s, enemy);
+    }
+
+    if s.bindings.pressed(Action::EndTurn) {
+        end_turn(s);
     }
 
     if let Some(wpos) = s.ui.right_click_menu_pos {
@@ -580,81 +771,35 @@ fn handle_input(s: &mut GameState) {
         let pos = s.camera.world_to_screen(wpos);
         egui::Area::new(egui::Id::new("context_menu"))
             .fixed_pos(egui::pos2(pos.x, pos.y))
-            .show(&egui(), |ui| {
-                egui::Frame::none()
-                    .fill(egui::Color32::BLACK)
-                    .show(ui, |ui| {
-                        for (name, sprite) in s.sprites.iter().sorted_by_key(|s| s.0) {
-                            if ui.button(name).clicked() {
-                                /*
-                                                commands().spawn((
-                                                    Transform::position(grid_world_pos(wpos)),
-                                                    Sprite::new(
-                                                        "tilemap".to_string(),
-                                                        vec2(1.0, 1.0),
-                                                        Z_UNIT,
-                                                        WHITE,
-                                                    )
-                                                    .with_rect(sprite.x, sprite.y, GRIDSIZE, GRIDSIZE),
-                                                ));
-                                */ // TODO new right click menu
-                            }
-                        }
-                    });
+            .show(egui(), |ui| {
+                egui::Frame::NONE.fill(egui::Color32::BLACK).show(ui, |ui| {
+                    if ui.button("End turn").clicked() {
+                        s.ui.right_click_menu_pos = None;
+                        end_turn(s);
+                    }
+                    if ui.button("Map editor").clicked() {
+                        s.ui.right_click_menu_pos = None;
+                        toggle_editor(s);
+                    }
+                });
             });
     }
 
     if let Some(e) = s.ui.selected_entity {
         if s.ui.move_state == MoveState::None {
-            let pos = s.entities[e].draw_pos;
-            let team = s.entities[e].team;
-
-            let start_pos = grid_pos(pos);
-            // handle move range
-            let mut move_range = Grid::new(s.grids.ground.width, s.grids.ground.height, 0);
-            move_range[start_pos] = 9;
-            dijkstra(&mut move_range, &[start_pos], movement_cost(s, PLAYER_TEAM));
-
-            // find goal
-            let mut grid = Grid::new(s.grids.ground.width, s.grids.ground.height, 0);
-            let goal = mouse_game_grid(s);
-            *grid.get_clamped_mut(goal.x, goal.y) = 99; // TODO increase this when done developing
-            dijkstra(&mut grid, &[goal], movement_cost(s, PLAYER_TEAM));
-            move_range.clamp_values(0, 1);
-            grid.mul_inplace(&move_range);
-
-            // allow passing through allies, but don't stop on them
-            let mut seeds = Vec::new();
-            for (_, actor) in &s.entities {
-                grid[actor.pos] = -99;
-                seeds.push(actor.pos);
-            }
-            let highest_reachable_pos = grid
-                .iter_coords()
-                .max_by_key(|(_pos, val)| *val)
-                .map(|(pos, _)| pos)
-                .unwrap();
-            seeds.push(highest_reachable_pos);
-            dijkstra(&mut grid, &seeds, movement_cost(s, PLAYER_TEAM));
-            grid.mul_inplace(&move_range);
-
-            // disallow moving through enemies
-            for (_, actor) in s.entities.iter().filter(|(_i, a)| a.team != team) {
-                grid[actor.pos] = -99;
-            }
-
-            // finally actually calculate and draw the path
-            let path = dijkstra_path(&grid, start_pos);
-            draw_move_range(s, &grid);
+            let path = path_to(&s.grids, &s.entities, e, s.ui.grid_cursor);
+            let reach = s.reach_cache.get(&s.grids, &s.entities, e);
+            draw_move_range(s, &reach);
             draw_move_path(s, &path);
 
-            //draw_dijkstra_map(&grid);
             if s.ui.draw_dijkstra_map {
-                draw_dijkstra_map(s, &grid);
+                draw_dijkstra_map(s, &reach.moves.reached_costs());
             }
 
-            if is_mouse_button_pressed(MouseButton::Left) && path.len() > 0 {
+            // moving onto the unit's own tile means standing on the spot
+            if (s.bindings.pressed(Action::Select) || confirm) && !path.is_empty() {
                 s.ui.move_state = MoveState::Moving;
+                s.ui.move_origin = Some(s.entities[e].pos);
                 s.co.queue(move |mut s| async move {
                     for pos in path.iter() {
                         let target = game_to_world(*pos);
@@ -664,7 +809,7 @@ fn handle_input(s: &mut GameState) {
                             {
                                 let s = &mut s.get();
                                 let drawpos = &mut s.entities[e].draw_pos;
-                                *drawpos = drawpos.lerp(target.into(), lerpiness);
+                                *drawpos = drawpos.lerp(target, lerpiness);
                             }
                             cosync::sleep_ticks(1).await;
                         }
@@ -672,58 +817,91 @@ fn handle_input(s: &mut GameState) {
                     let last = *path.last().unwrap();
                     let target = game_to_world(last);
                     let s = &mut s.get();
-                    s.entities[e].draw_pos = target.into();
+                    s.entities[e].draw_pos = target;
                     s.entities[e].pos = last;
+                    s.reach_cache.invalidate();
                     s.ui.move_state = MoveState::Confirm;
+                    s.ui.menu_index = 0;
                 });
             }
-            if is_key_pressed(KeyCode::Space) {
-                // stand on the spot and attack or wait
-                s.ui.move_state = MoveState::Confirm;
-            }
-            s.ui.cursor_pos = Some(pos.into());
+            s.ui.cursor_pos = Some(game_to_world(s.ui.grid_cursor).into());
         }
         if s.ui.move_state == MoveState::Confirm {
+            // check if unit from other team is in range
+            let enemies = enemies_in_range(&s.grids, &s.entities, e);
+            let options = if enemies.is_empty() { 1 } else { 2 };
+            if let Some(step) = cursor_step(&s.bindings) {
+                s.ui.menu_index = (s.ui.menu_index as i32 + step.y).rem_euclid(options) as usize;
+            }
+            // TODO right click should reset to start as well
+            if s.bindings.pressed(Action::Cancel) {
+                let origin = s.ui.move_origin.take().unwrap_or(s.entities[e].pos);
+                s.entities[e].pos = origin;
+                s.reach_cache.invalidate();
+                s.entities[e].draw_pos = game_to_world(origin);
+                s.ui.grid_cursor = origin;
+                s.ui.move_state = MoveState::None;
+                return;
+            }
+
             let pos = s.camera.world_to_screen(s.entities[e].draw_pos);
             egui::Area::new(egui::Id::new("move confirmation"))
                 .fixed_pos(egui::pos2(pos.x, pos.y))
-                .show(&egui(), |ui| {
-                    egui::Frame::none()
-                        .fill(egui::Color32::BLACK)
-                        .show(ui, |ui| {
-                            // TODO escape or right click reset to start
-                            if ui.button("Wait").clicked() {
-                                let e = s.ui.selected_entity.take().unwrap();
-                                s.entities[e].has_moved = true;
-                                s.ui.move_state = MoveState::None;
-                            }
-
-                            // check if unit from other team is in range
-                            let enemies = enemies_in_range(s, e);
-                            if enemies.len() > 0 {
-                                if ui.button("Attack").clicked() {
-                                    s.ui.move_state = MoveState::ChooseAttack;
-                                }
-                            }
-                        })
+                .show(egui(), |ui| {
+                    egui::Frame::NONE.fill(egui::Color32::BLACK).show(ui, |ui| {
+                        let mut button = |ui: &mut egui::Ui, index: usize, text: &str| {
+                            let selected = s.ui.menu_index == index;
+                            let button = egui::Button::new(text).selected(selected);
+                            ui.add(button).clicked() || (selected && confirm)
+                        };
+                        let wait = button(ui, 0, "Wait");
+                        let attack = !enemies.is_empty() && button(ui, 1, "Attack");
+                        if wait {
+                            let e = s.ui.selected_entity.take().unwrap();
+                            s.entities[e].has_moved = true;
+                            s.ui.move_state = MoveState::None;
+                        } else if attack {
+                            choose_attack(&mut s.ui, &enemies, &mut confirm);
+                        }
+                    })
                 });
+            s.ui.cursor_pos = Some(s.entities[e].draw_pos.into());
         }
         if s.ui.move_state == MoveState::ChooseAttack {
-            let enemies = enemies_in_range(s, e);
-            let chosen = s.ui.chosen_enemy.unwrap_or(0);
-            let enemy = enemies[chosen];
+            let enemies = enemies_in_range(&s.grids, &s.entities, e);
+            let chosen = s.ui.chosen_enemy.unwrap_or(0) % enemies.len();
+            let hovered = enemies
+                .iter()
+                .find(|(_, pos)| *pos == s.ui.grid_cursor)
+                .copied();
 
-            // TODO
-            if is_key_pressed(KeyCode::Escape) {
-                // TODO revert one step instead
-                s.ui.move_state = MoveState::None;
-                s.ui.selected_entity = None;
+            draw_attack_targets(s, &enemies, enemies[chosen]);
+            // the forecast follows the cursor, falling back to the last choice
+            let shown = hovered.unwrap_or(enemies[chosen]);
+            draw_attack_forecast(s, e, shown);
+            draw_forecast_panel(s, e, shown.0);
+
+            if s.bindings.pressed(Action::Cancel) {
+                // back to the confirm menu
+                s.ui.move_state = MoveState::Confirm;
+                s.ui.chosen_enemy = None;
+                return;
             }
-            if is_key_pressed(KeyCode::A) {
-                s.ui.chosen_enemy = Some((chosen + 1) % enemies.len());
-                println!("Switch enemy to {:?}", s.ui.chosen_enemy);
+            if let Some(step) = cursor_step(&s.bindings) {
+                // right and down pick the next target, left and up the previous one
+                let current = enemies.iter().position(|x| *x == shown).unwrap_or(chosen);
+                let offset = if step.x + step.y > 0 {
+                    1
+                } else {
+                    enemies.len() - 1
+                };
+                let next = (current + offset) % enemies.len();
+                s.ui.chosen_enemy = Some(next);
+                s.ui.grid_cursor = enemies[next].1;
             }
-            if is_key_pressed(KeyCode::Space) {
+            let clicked = hovered.filter(|_| s.bindings.pressed(Action::Select));
+            let target = if confirm { Some(shown) } else { clicked };
+            if let Some(enemy) = target {
                 s.ui.move_state = MoveState::Attacking;
                 s.ui.chosen_enemy = None;
                 s.co.queue(move |mut s| async move {
@@ -735,11 +913,307 @@ fn handle_input(s: &mut GameState) {
                     s.ui.move_state = MoveState::None;
                 });
             }
-            s.ui.cursor_pos = Some(game_to_world(enemy.1).into());
+            s.ui.cursor_pos = Some(game_to_world(shown.1).into());
         }
     } else {
-        s.ui.cursor_pos = Some(s.camera.mouse_world().into());
+        s.ui.cursor_pos = Some(game_to_world(s.ui.grid_cursor).into());
+    }
+}
+
+/// leaves the confirm menu for the target selection
+/// the confirm press is used up here, it would attack the first target in the same frame otherwise
+fn choose_attack(ui: &mut UIState, enemies: &[(ActorKey, IVec2)], confirm: &mut bool) {
+    ui.move_state = MoveState::ChooseAttack;
+    ui.grid_cursor = enemies[0].1;
+    *confirm = false;
+}
+
+fn end_turn(s: &mut GameState) {
+    s.phase = GamePhase::EnemyPhase;
+    s.co.queue(enemy_phase);
+}
+
+/// where a context menu should open, in world coordinates
+/// a long press is the right click of touch screens
+fn context_menu_requested(s: &GameState, gestures: &touch::Gestures) -> Option<Vec2> {
+    s.bindings
+        .released(Action::ContextMenu)
+        .then(mouse_position)
+        .map(Vec2::from)
+        .or(gestures.long_press)
+        .map(|pos| s.camera.screen_to_world(pos).into())
+}
+
+/// switches between playing and editing the map
+/// the editor can only be entered while the player could act
+fn toggle_editor(s: &mut GameState) {
+    match s.phase {
+        GamePhase::Editor => s.phase = GamePhase::PlayerPhase,
+        GamePhase::PlayerPhase if s.ui.move_state == MoveState::None => {
+            s.phase = GamePhase::Editor;
+            s.ui.selected_entity = None;
+            s.ui.inspected_enemy = None;
+            s.ui.right_click_menu_pos = None;
+            s.ui.editor_size = (s.grids.ground.width, s.grids.ground.height);
+        }
+        _ => {}
+    }
+}
+
+/// map editor: the select button paints the brush, right click picks one
+fn handle_editor_input(s: &mut GameState) {
+    let gestures = handle_pointer_input(s);
+    let tap = gestures
+        .tap
+        .filter(|_| !egui().is_pointer_over_area())
+        .map(|pos| screen_game_grid(s, pos));
+    if let Some(pos) = tap {
+        s.ui.grid_cursor = pos;
+    }
+    if let Some(step) = cursor_step(&s.bindings) {
+        move_cursor(s, s.ui.grid_cursor + step);
+    }
+    if let Some(pos) = context_menu_requested(s, &gestures) {
+        s.ui.right_click_menu_pos = Some(pos);
+    }
+    if s.bindings.pressed(Action::Cancel) {
+        s.ui.right_click_menu_pos = None;
     }
+
+    let over_ui = egui().is_pointer_over_area();
+    let drag = s.bindings.down(Action::Select) && !over_ui;
+    let menu_open = s.ui.right_click_menu_pos.is_some();
+    if (drag && !menu_open) || tap.is_some() || s.bindings.pressed(Action::Confirm) {
+        let brush = s.ui.brush.clone();
+        let pos = s.ui.grid_cursor;
+        if apply_brush(&mut s.grids, &mut s.entities, &s.entity_defs, &brush, pos) {
+            s.reach_cache.invalidate();
+            rebuild_tile_sprites(s);
+        }
+    }
+    // clicking next to the menu closes it
+    if s.bindings.pressed(Action::Select) && !over_ui {
+        s.ui.right_click_menu_pos = None;
+    }
+
+    draw_editor_window(s);
+
+    if let Some(wpos) = s.ui.right_click_menu_pos {
+        let pos = s.camera.world_to_screen(wpos);
+        let brushes = Brush::all(&s.entity_defs.keys().cloned().sorted().collect_vec());
+        egui::Area::new(egui::Id::new("brush menu"))
+            .fixed_pos(egui::pos2(pos.x, pos.y))
+            .show(egui(), |ui| {
+                egui::Frame::NONE.fill(egui::Color32::BLACK).show(ui, |ui| {
+                    for brush in brushes {
+                        let selected = s.ui.brush == brush;
+                        if ui.selectable_label(selected, brush.label()).clicked() {
+                            s.ui.brush = brush;
+                            s.ui.right_click_menu_pos = None;
+                        }
+                    }
+                });
+            });
+    }
+    s.ui.cursor_pos = Some(game_to_world(s.ui.grid_cursor).into());
+}
+
+/// brushes, map size and saving and loading
+fn draw_editor_window(s: &mut GameState) {
+    let brushes = Brush::all(&s.entity_defs.keys().cloned().sorted().collect_vec());
+    egui::Window::new("map editor").show(egui(), |ui| {
+        ui.label("brush (right click on the map to pick one)");
+        for brush in brushes {
+            let label = brush.label();
+            ui.radio_value(&mut s.ui.brush, brush, label);
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            let (width, height) = &mut s.ui.editor_size;
+            ui.label("size");
+            ui.add(egui::DragValue::new(width).range(1..=64));
+            ui.add(egui::DragValue::new(height).range(1..=64));
+            if ui.button("resize").clicked() {
+                let (width, height) = s.ui.editor_size;
+                resize_map(&mut s.grids, &mut s.entities, width, height);
+                s.reach_cache.invalidate();
+                rebuild_tile_sprites(s);
+            }
+        });
+
+        ui.separator();
+        ui.label("generate a skirmish map");
+        ui.horizontal(|ui| {
+            let config = &mut s.ui.map_gen;
+            ui.label("units");
+            ui.add(egui::DragValue::new(&mut config.units_per_team).range(1..=16));
+            ui.label("lakes");
+            ui.add(egui::DragValue::new(&mut config.lakes).range(0..=8));
+            ui.label("forests");
+            ui.add(egui::DragValue::new(&mut config.forests).range(0..=8));
+            ui.checkbox(&mut config.river, "river");
+        });
+        ui.horizontal(|ui| {
+            ui.label("seed");
+            ui.add(egui::DragValue::new(&mut s.ui.map_seed));
+            if ui.button("generate").clicked() {
+                // uses the size from above
+                let (width, height) = s.ui.editor_size;
+                let config = MapGenConfig {
+                    width,
+                    height,
+                    ..s.ui.map_gen.clone()
+                };
+                match mapgen::generate(s.ui.map_seed, &config, &s.entity_defs) {
+                    Ok(generated) => load_map(s, generated.map),
+                    Err(e) => eprintln!("could not generate a map: {}", e),
+                }
+            }
+        });
+
+        ui.separator();
+        ui.add(egui::TextEdit::singleline(&mut s.ui.map_path).hint_text("maps/my_map.json"));
+        ui.horizontal(|ui| {
+            if ui.button("save").clicked() {
+                let map = GameMap {
+                    grids: s.grids.clone(),
+                    actors: s.entities.values().cloned().collect(),
+                };
+                match map.save(&s.ui.map_path) {
+                    Ok(()) => println!("Saved map to {}.", s.ui.map_path),
+                    Err(e) => eprintln!("could not save map '{}': {}", s.ui.map_path, e),
+                }
+            }
+            if ui.button("load").clicked() {
+                match GameMap::load(&s.ui.map_path) {
+                    Ok(map) => {
+                        load_map(s, map);
+                        s.ui.editor_size = (s.grids.ground.width, s.grids.ground.height);
+                    }
+                    Err(e) => eprintln!("could not load map '{}': {}", s.ui.map_path, e),
+                }
+            }
+        });
+
+        ui.separator();
+        if ui.button("back to the game").clicked() {
+            toggle_editor(s);
+        }
+    });
+}
+
+/// camera controls and the cursor following the mouse, shared by play and editor mode
+fn handle_pointer_input(s: &mut GameState) -> touch::Gestures {
+    if s.bindings.down(Action::DragPan) {
+        s.camera.mouse_delta(s.ui.last_mouse_pos, mouse_position());
+    }
+
+    let mouse_moved = Vec2::from(s.ui.last_mouse_pos) != Vec2::from(mouse_position());
+    s.ui.last_mouse_pos = mouse_position().into();
+    let mouse_clicked = s.bindings.pressed(Action::Select) || s.bindings.released(Action::Select);
+    if mouse_moved || mouse_clicked {
+        s.ui.grid_cursor = mouse_game_grid(s);
+    }
+    let (_x, y) = mouse_wheel();
+    if y > 0. {
+        s.camera.zoom(1);
+    }
+    if y < 0. {
+        s.camera.zoom(-1);
+    }
+
+    let gestures = s.touch.update(&touches(), get_time());
+    if let Some((old, new)) = gestures.pan {
+        s.camera.mouse_delta(old, new);
+    }
+    if gestures.zoom != 0 {
+        s.camera.zoom(gestures.zoom);
+    }
+    if s.bindings.pressed(Action::PanUp) {
+        s.camera.move_camera((0., -32.));
+    }
+    if s.bindings.pressed(Action::PanDown) {
+        s.camera.move_camera((0., 32.));
+    }
+    if s.bindings.pressed(Action::PanLeft) {
+        s.camera.move_camera((-32., 0.));
+    }
+    if s.bindings.pressed(Action::PanRight) {
+        s.camera.move_camera((32., 0.));
+    }
+    gestures
+}
+
+/// one tile step from the cursor actions
+fn cursor_step(bindings: &Keybindings) -> Option<IVec2> {
+    [
+        (Action::CursorUp, ivec2(0, -1)),
+        (Action::CursorDown, ivec2(0, 1)),
+        (Action::CursorLeft, ivec2(-1, 0)),
+        (Action::CursorRight, ivec2(1, 0)),
+    ]
+    .into_iter()
+    .find(|(action, _)| bindings.pressed(*action))
+    .map(|(_, step)| step)
+}
+
+/// puts the grid cursor on `pos` if that is on the map and scrolls it into view
+fn move_cursor(s: &mut GameState, pos: IVec2) {
+    if !s.grids.ground.is_valid(pos) {
+        return;
+    }
+    s.ui.grid_cursor = pos;
+    let center = s.display_pos(game_to_world(pos) + Vec2::splat(GRIDSIZE as f32 / 2.));
+    s.camera.keep_in_view(center, GRIDSIZE as f32 * 2.);
+}
+
+/// selects the next player unit that hasn't moved yet and puts the cursor on it
+fn select_next_unit(s: &mut GameState) {
+    let units = s
+        .entities
+        .iter()
+        .filter(|(_, a)| a.team == PLAYER_TEAM && !a.has_moved)
+        .map(|(key, _)| key)
+        .collect_vec();
+    let current =
+        s.ui.selected_entity
+            .and_then(|e| units.iter().position(|u| *u == e));
+    let next = match current {
+        Some(i) => units[(i + 1) % units.len()],
+        None => match units.first() {
+            Some(unit) => *unit,
+            None => return,
+        },
+    };
+    s.ui.selected_entity = Some(next);
+    move_cursor(s, s.entities[next].pos);
+}
+
+/// the bot gets asked without blocking the frame, while waiting the game keeps drawing
+/// the built-in ai decides if there is no bot or it fails to answer
+async fn enemy_action(s: &mut cosync::CosyncInput<GameState>, index: ActorKey) -> UnitAction {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let question = {
+            let mut guard = s.get();
+            let s = &mut *guard;
+            let bot = s.enemy.bot.as_mut();
+            bot.and_then(|bot| bot.ask(&s.grids, &s.entities, index))
+        };
+        if let Some(question) = question {
+            loop {
+                let reply = s.get().enemy.bot.as_mut().map(|bot| bot.poll(&question));
+                match reply {
+                    Some(bot::BotReply::Waiting) => cosync::sleep_ticks(1).await,
+                    Some(bot::BotReply::Done(Some(action))) => return action,
+                    _ => break,
+                }
+            }
+        }
+    }
+    let s = s.get();
+    ai::decide(&s.grids, &s.entities, index, &s.enemy.config)
 }
 
 async fn enemy_phase(mut s: cosync::CosyncInput<GameState>) {
@@ -756,77 +1230,29 @@ async fn enemy_phase(mut s: cosync::CosyncInput<GameState>) {
         .map(|e| e.0)
         .collect_vec();
     for index in ai_units {
-        let (cursor, move_range, path, _grid) = {
-            let s = &mut s.get();
-            let actor = &s.entities[index];
-            let start_pos = actor.pos;
-
-            // handle move range
-            let mut move_range = Grid::new(s.grids.ground.width, s.grids.ground.height, 0);
-            move_range[start_pos] = 9;
-            dijkstra(&mut move_range, &[start_pos], movement_cost(s, ENEMY_TEAM));
-            move_range.clamp_values(0, 1);
-
-            // find goal position
-            let mut grid = Grid::new(s.grids.ground.width, s.grids.ground.height, 0);
-            let enemy_positions = s
-                .entities
-                .iter()
-                .filter(|(_i, a)| a.team == PLAYER_TEAM)
-                .map(|(_i, a)| a.pos)
-                .collect_vec();
-            for pos in enemy_positions.iter() {
-                grid[*pos] = 30;
-            }
-            dijkstra(&mut grid, &enemy_positions, movement_cost(s, ENEMY_TEAM));
-            grid.mul_inplace(&move_range);
-
-            // allow passing through allies, but don't stop on them
-            // stop on your current position if its already the best
-            for (_, actor) in s.entities.iter().filter(|(i, _)| *i != index) {
-                grid[actor.pos] = -99;
-            }
-
-            let mut highest_reachable_pos = grid
-                .iter_coords()
-                .max_by_key(|(_pos, val)| *val)
-                .map(|(pos, _)| pos)
-                .unwrap();
-            // stop weird running around that depends on grid iter order
-            if grid[highest_reachable_pos] == grid[start_pos] {
-                highest_reachable_pos = start_pos;
-            }
-
-            let mut grid = Grid::new(s.grids.ground.width, s.grids.ground.height, 0);
-            grid[highest_reachable_pos] = 30;
-            dijkstra(
-                &mut grid,
-                &[highest_reachable_pos],
-                movement_cost(s, ENEMY_TEAM),
-            );
-            grid.mul_inplace(&move_range);
-            dbg!(highest_reachable_pos);
-
-            let debug_grid = grid.clone();
-
-            // compute path
-            let path = dijkstra_path(&grid, start_pos);
-
+        let action = enemy_action(&mut s, index).await;
+        let (cursor, reach, path) = {
+            let mut guard = s.get();
+            let s = &mut *guard;
+            let reach = s.reach_cache.get(&s.grids, &s.entities, index);
+            let path = reach.path_to(action.move_to).unwrap_or_default();
             // results for async usage
-            (actor.draw_pos, move_range, path, debug_grid)
+            (s.entities[index].draw_pos, reach, path)
         };
         for _ in 0..tweak!(20) {
             {
                 let s = &mut s.get();
-                draw_move_range(s, &move_range);
+                draw_move_range(s, &reach);
                 s.ui.cursor_pos = Some(cursor.into());
                 draw_move_path(s, &path);
-                draw_dijkstra_map(s, &_grid);
+                if s.ui.draw_dijkstra_map {
+                    draw_dijkstra_map(s, &reach.moves.reached_costs());
+                }
             }
             cosync::sleep_ticks(1).await;
         }
         // move along path
-        {
+        if !path.is_empty() {
             for pos in path.iter() {
                 let target = game_to_world(*pos);
                 let mut lerpiness = 0.;
@@ -844,24 +1270,20 @@ async fn enemy_phase(mut s: cosync::CosyncInput<GameState>) {
             let s = &mut s.get();
             s.entities[index].draw_pos = target;
             s.entities[index].pos = *path.last().unwrap();
+            s.reach_cache.invalidate();
         }
 
         // attack player if close
-        {
+        if let Some(pos) = action.attack {
             cosync::sleep_ticks(20).await;
             for _ in 0..20 {
                 cosync::sleep_ticks(1).await;
-                let s = &mut s.get();
-                if let Some((_enemy, pos)) = enemies_in_range(s, index).first() {
-                    s.ui.cursor_pos = Some(game_to_world(*pos).into());
-                }
+                s.get().ui.cursor_pos = Some(game_to_world(pos).into());
             }
 
-            if let Some(enemy) = {
-                let s = &mut s.get();
-                enemies_in_range(s, index).first()
-            } {
-                animate_attack(&mut s, index, *enemy).await;
+            let enemy = actor_at_pos(&s.get().entities, pos);
+            if let Some(enemy) = enemy {
+                animate_attack(&mut s, index, (enemy, pos)).await;
             }
         }
 
@@ -880,7 +1302,7 @@ async fn enemy_phase(mut s: cosync::CosyncInput<GameState>) {
 /// debug information and keybindings
 /// also does drawing in immediate mode
 fn handle_debug_input(s: &mut GameState) {
-    egui::Window::new("kf_debug_info").show(&egui(), |ui| {
+    egui::Window::new("kf_debug_info").show(egui(), |ui| {
         let pos = grid_world_pos(s.camera.mouse_world());
         ui.label(format!("mouse world grid pos: {}", pos));
         let pos = ivec2(pos.x as _, -pos.y as _);
@@ -902,7 +1324,32 @@ fn handle_debug_input(s: &mut GameState) {
             ui.label("None");
         }
         ui.separator();
+        ui.checkbox(&mut s.ui.show_keybindings, "keybindings");
         ui.label(format!("Move State: {:?}", s.ui.move_state));
+        ui.horizontal(|ui| {
+            ui.label("topology:");
+            for topology in Topology::ALL {
+                let label = format!("{:?}", topology);
+                if ui
+                    .radio_value(&mut s.grids.topology, topology, label)
+                    .changed()
+                {
+                    s.reach_cache.invalidate();
+                }
+            }
+        });
+        // with the move costs of the selected unit, like the dijkstra map
+        if ui.button("copy board").clicked() {
+            let costs = s.ui.selected_entity.map(|e| {
+                s.reach_cache
+                    .get(&s.grids, &s.entities, e)
+                    .moves
+                    .reached_costs()
+            });
+            let board = ascii_map::dump_board(&s.grids, &s.entities, costs.as_ref());
+            ui.ctx().copy_text(board);
+            println!("Copied the board to the clipboard.");
+        }
 
         ui.separator();
         ui.label("Entitiy transforms:");
@@ -914,37 +1361,294 @@ fn handle_debug_input(s: &mut GameState) {
         }
     });
 
-    if is_key_pressed(KeyCode::L) {
+    // keys pressed for rebinding shouldn't toggle anything
+    let keys = s.ui.rebinding.is_none();
+    if keys && s.bindings.pressed(Action::ToggleDijkstraMap) {
         s.ui.draw_dijkstra_map = !s.ui.draw_dijkstra_map;
     }
 
-    if is_key_pressed(KeyCode::M) {
+    if keys && s.bindings.pressed(Action::ToggleAiMap) {
         s.ui.draw_ai_map = !s.ui.draw_ai_map;
     }
 
+    if s.ui.draw_ai_map {
+        draw_ai_map(s);
+    }
+
+    if keys && s.bindings.pressed(Action::ToggleFov) {
+        s.ui.draw_fov = !s.ui.draw_fov;
+    }
+    if s.ui.draw_fov {
+        draw_fov_debug(s);
+    }
+
     cw_draw_debug_window();
 }
 
+/// rebinding screen: every action with its bindings,
+/// clicking a binding removes it, "+" waits for a key or click to add
+fn draw_keybindings(s: &mut GameState) {
+    if let Some(action) = s.ui.rebinding {
+        // clicks on the window itself don't count
+        let pressed = keybindings::pressed_binding()
+            .filter(|b| matches!(b, Binding::Key { .. }) || !egui().is_pointer_over_area());
+        if let Some(binding) = pressed {
+            // escape aborts instead of being bound
+            if binding != Binding::key(KeyCode::Escape) {
+                s.bindings.bind(action, binding);
+            }
+            s.bindings.swallow(binding);
+            s.ui.rebinding = None;
+        }
+    }
+
+    let mut open = s.ui.show_keybindings;
+    egui::Window::new("keybindings")
+        .open(&mut open)
+        .show(egui(), |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("keybindings grid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for action in Action::ALL {
+                            ui.label(format!("{:?}", action));
+                            ui.horizontal(|ui| {
+                                for binding in s.bindings.get(action).to_vec() {
+                                    let button = ui.button(binding.label());
+                                    if button.on_hover_text("remove").clicked() {
+                                        s.bindings.unbind(action, binding);
+                                    }
+                                }
+                                let waiting = s.ui.rebinding == Some(action);
+                                let text = if waiting {
+                                    "press a key, escape aborts..."
+                                } else {
+                                    "+"
+                                };
+                                if ui.selectable_label(waiting, text).clicked() {
+                                    s.ui.rebinding = (!waiting).then_some(action);
+                                }
+                            });
+                            let conflicts = s
+                                .bindings
+                                .conflicts(action)
+                                .iter()
+                                .map(|(binding, other)| {
+                                    format!("{} is also {:?}", binding.label(), other)
+                                })
+                                .join(", ");
+                            ui.colored_label(egui::Color32::RED, conflicts);
+                            ui.end_row();
+                        }
+                    });
+            });
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("save").clicked() {
+                    match s.bindings.save(KEYBINDINGS_PATH) {
+                        Ok(()) => println!("Saved keybindings to {}.", KEYBINDINGS_PATH),
+                        Err(e) => eprintln!("could not save {}: {}", KEYBINDINGS_PATH, e),
+                    }
+                }
+                if ui.button("reset to defaults").clicked() {
+                    s.bindings = Keybindings::default();
+                }
+            });
+        });
+    s.ui.show_keybindings = open;
+}
+
+/// heat overlay of the influence maps: red and blue for the damage each team could deal
+fn draw_ai_map(s: &mut GameState) {
+    let influence = s.reach_cache.influence(&s.grids, &s.entities);
+    let size = GRIDSIZE as f32;
+    for (pos, red) in influence.red.iter_coords() {
+        let blue = influence.blue[pos];
+        if *red == 0 && blue == 0 {
+            continue;
+        }
+        let heat = |v: i32| v as f32 / HP_MAX as f32;
+        let color = Color::new(heat(*red), 0., heat(blue), 0.5);
+        s.draw_rect(game_to_world(pos), size, size, Z_DIJKSTRA_DEBUG, color);
+    }
+}
+
+/// darkens what the selected unit (or the mouse tile) can't see
+/// and shows the line of sight from the unit to the mouse
+fn draw_fov_debug(s: &GameState) {
+    let mouse = mouse_game_grid(s);
+    let unit = s.ui.selected_entity.map(|e| s.entities[e].pos);
+    let origin = unit.unwrap_or(mouse);
+    let fov = s
+        .grids
+        .terrain
+        .field_of_view(origin, VISION_RANGE, blocks_sight);
+    let shadow = Color::new(0., 0., 0.1, 0.5);
+    let size = GRIDSIZE as f32;
+    for (pos, visible) in fov.iter_coords() {
+        if !visible {
+            s.draw_rect(game_to_world(pos), size, size, Z_DIJKSTRA_DEBUG, shadow);
+        }
+    }
+    if let Some(unit) = unit {
+        let color = if s.grids.terrain.line_of_sight(unit, mouse, blocks_sight) {
+            GREEN
+        } else {
+            RED
+        };
+        let center = |pos: IVec2| game_to_world(pos) + Vec2::splat(GRIDSIZE as f32 / 2.);
+        s.draw_line(center(unit), center(mouse), 1., Z_DIJKSTRA_DEBUG, color);
+    }
+}
+
 fn draw_cursor(s: &GameState, pos: Vec2) {
     s.draw_sprite("cursor", grid_world_pos(pos), Z_CURSOR, WHITE);
 }
 
-fn draw_move_range(s: &GameState, grid: &Grid<i32>) {
-    for (x, y, v) in grid.iter() {
-        if *v > 0 {
-            let pos = ivec2(x, y);
-            let pos = game_to_world(pos);
-            s.draw_sprite("move_range", pos, Z_MOVE_HIGHLIGHT, WHITE);
+fn draw_move_range(s: &GameState, reach: &Reachability) {
+    for pos in reach.reachable_tiles() {
+        let pos = game_to_world(pos);
+        s.draw_sprite("move_range", pos, Z_MOVE_HIGHLIGHT, WHITE);
+    }
+}
+
+/// every tile any enemy could attack during its next turn
+fn draw_threat_range(s: &mut GameState) {
+    let mut threat = Grid::new(s.grids.ground.width, s.grids.ground.height, 0);
+    let enemies = s
+        .entities
+        .iter()
+        .filter(|(_k, a)| a.team == ENEMY_TEAM)
+        .map(|(k, _a)| k)
+        .collect_vec();
+    for enemy in enemies {
+        let reach = s.reach_cache.get(&s.grids, &s.entities, enemy);
+        threat = threat.add(&reach_threat(&s.grids, &reach));
+    }
+    let size = GRIDSIZE as f32;
+    for (pos, count) in threat.iter_coords() {
+        if *count > 0 {
+            let color = Color::new(1., 0., 0., 0.25);
+            s.draw_rect(game_to_world(pos), size, size, Z_MOVE_HIGHLIGHT, color);
         }
     }
 }
 
-fn draw_move_path(s: &GameState, path: &Vec<IVec2>) {
+/// where a single enemy can move to, and what it could attack from there
+fn draw_enemy_range(s: &mut GameState, enemy: ActorKey) {
+    let reach = s.reach_cache.get(&s.grids, &s.entities, enemy);
+    let threat = reach_threat(&s.grids, &reach);
+    let size = GRIDSIZE as f32;
+    for (pos, attackable) in threat.iter_coords() {
+        let color = if reach.is_reachable(pos) {
+            Color::new(1., 0., 0., 0.5)
+        } else if *attackable > 0 {
+            Color::new(1., 0.4, 0.4, 0.35)
+        } else {
+            continue;
+        };
+        s.draw_rect(game_to_world(pos), size, size, Z_MOVE_HIGHLIGHT, color);
+    }
+}
+
+/// marks every enemy that can be attacked, the chosen one stronger
+fn draw_attack_targets(s: &GameState, enemies: &[(ActorKey, IVec2)], chosen: (ActorKey, IVec2)) {
+    let size = GRIDSIZE as f32;
+    for (enemy, pos) in enemies {
+        let alpha = if *enemy == chosen.0 { 0.7 } else { 0.4 };
+        let color = Color::new(1., 0.5, 0., alpha);
+        s.draw_rect(game_to_world(*pos), size, size, Z_MOVE_HIGHLIGHT, color);
+    }
+}
+
+/// expected damage and counter damage next to the target
+fn draw_attack_forecast(s: &GameState, attacker: ActorKey, enemy: (ActorKey, IVec2)) {
+    let forecast = forecast_attack(&s.entities, attacker, enemy.0);
+    let (attacker_hp, enemy_hp) = (s.entities[attacker].hp, s.entities[enemy.0].hp);
+    let pos = s.display_pos(game_to_world(enemy.1 + ivec2(1, 0)));
+    let pos = s.camera.world_to_screen(pos);
+    egui::Area::new(egui::Id::new("attack forecast"))
+        .fixed_pos(egui::pos2(pos.x, pos.y))
+        .show(egui(), |ui| {
+            egui::Frame::NONE.fill(egui::Color32::BLACK).show(ui, |ui| {
+                ui.label(format!(
+                    "damage {}: {} -> {} hp",
+                    forecast.damage, enemy_hp, forecast.defender_hp_after
+                ));
+                if forecast.counter_damage > 0 {
+                    ui.label(format!(
+                        "counter {}: {} -> {} hp",
+                        forecast.counter_damage, attacker_hp, forecast.attacker_hp_after
+                    ));
+                } else {
+                    ui.label("no counter");
+                }
+            })
+        });
+}
+
+/// attacker and defender side by side with the outcome of the attack
+fn draw_forecast_panel(s: &GameState, attacker: ActorKey, defender: ActorKey) {
+    let forecast = forecast_attack(&s.entities, attacker, defender);
+    let side = |ui: &mut egui::Ui, unit: ActorKey, hp_after: i32| {
+        let actor = &s.entities[unit];
+        ui.vertical(|ui| {
+            if let Some(tilemap) = &s.tilemap {
+                draw_portrait(ui, tilemap, &s.sprites[&actor.sprite_name]);
+            }
+            ui.label(format!("{:?} {:?}", actor.team, actor.unit_type));
+            ui.label(format!("hp {} -> {}", actor.hp, hp_after));
+        });
+    };
+    egui::Window::new("attack forecast")
+        .anchor(egui::Align2::RIGHT_BOTTOM, [-8., -8.])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui(), |ui| {
+            ui.horizontal(|ui| {
+                side(ui, attacker, forecast.attacker_hp_after);
+                ui.separator();
+                side(ui, defender, forecast.defender_hp_after);
+            });
+            ui.separator();
+            ui.label(format!("damage: {}", forecast.damage));
+            ui.label(format!("terrain defense: {}%", forecast.terrain_defense));
+            if forecast.counter_damage > 0 {
+                ui.label(format!("counter attack: {}", forecast.counter_damage));
+            } else {
+                ui.label("no counter attack");
+            }
+        });
+}
+
+fn draw_portrait(ui: &mut egui::Ui, tilemap: &Texture2D, sprite: &Sprite) {
+    let size = tilemap.size();
+    let source = sprite.src;
+    let uv = egui::Rect::from_min_size(
+        egui::pos2(source.x / size.x, source.y / size.y),
+        egui::vec2(source.w / size.x, source.h / size.y),
+    );
+    // without a texture the panel just goes without portraits
+    if let Some(texture) = egui_macroquad::texture_id(tilemap) {
+        ui.add(egui::Image::new((texture, egui::vec2(48., 48.))).uv(uv));
+    }
+}
+
+fn draw_move_path(s: &GameState, path: &[IVec2]) {
     const DOWN: (i32, i32) = (0, 1);
     const UP: (i32, i32) = (0, -1);
     const RIGHT: (i32, i32) = (1, 0);
     const LEFT: (i32, i32) = (-1, 0);
 
+    if s.grids.topology != Topology::Orthogonal {
+        // the arrow sprites only cover the 4 orthogonal directions
+        let center = |pos: IVec2| game_to_world(pos) + Vec2::splat(GRIDSIZE as f32 / 2.);
+        for (from, to) in path.iter().tuple_windows() {
+            s.draw_line(center(*from), center(*to), 2., Z_MOVE_ARROW, WHITE);
+        }
+        return;
+    }
+
     let mut iter = path.iter();
     let prev = iter.next().cloned();
     let mut prev_direction: Option<(i32, i32)> = None;
@@ -985,31 +1689,6 @@ fn draw_move_path(s: &GameState, path: &Vec<IVec2>) {
     }
 }
 
-fn movement_cost<'a>(s: &'a GameState, team: Team) -> impl Fn(IVec2) -> i32 + 'a {
-    let blocked: HashSet<IVec2> = s
-        .entities
-        .iter()
-        .filter_map(|(_i, e)| if e.team != team { Some(e.pos) } else { None })
-        .collect();
-
-    let cost_function = move |pos| -> i32 {
-        if blocked.contains(&pos) {
-            return 9999;
-        }
-        let ground = *s.grids.ground.get_clamped_v(pos);
-        let terrain = *s.grids.terrain.get_clamped_v(pos);
-        use GroundType as G;
-        use TerrainType as T;
-        match (ground, terrain) {
-            (G::Water, _) => 9999,
-            (G::Ground, T::None) => 2,
-            (G::Ground, T::Street) => 1,
-            (G::Ground, T::Forest) => 3,
-        }
-    };
-    cost_function
-}
-
 /// rounds pos to align with grid
 fn grid_world_pos(v: Vec2) -> Vec2 {
     let mut pos = grid_pos(v);
@@ -1034,12 +1713,14 @@ fn world_to_game(v: Vec2) -> IVec2 {
     ivec2(v.x as i32 / GRIDSIZE, v.y as i32 / GRIDSIZE)
 }
 
-fn game_to_world(v: IVec2) -> Vec2 {
-    vec2((v.x * GRIDSIZE) as f32, (v.y * GRIDSIZE) as f32).into()
+fn mouse_game_grid(s: &GameState) -> IVec2 {
+    screen_game_grid(s, mouse_position().into())
 }
 
-fn mouse_game_grid(s: &GameState) -> IVec2 {
-    world_to_game(s.camera.mouse_world())
+/// tile at a position on the screen, in pixels
+fn screen_game_grid(s: &GameState, pos: Vec2) -> IVec2 {
+    let world = s.camera.screen_to_world(pos).into();
+    world_to_game(s.grids.topology.world_pos(world, GRIDSIZE as f32))
 }
 
 fn draw_dijkstra_map(s: &GameState, grid: &Grid<i32>) {
@@ -1050,8 +1731,15 @@ fn draw_dijkstra_map(s: &GameState, grid: &Grid<i32>) {
             b: 0.1,
             a: 0.5,
         };
-        let pos = game_to_world(ivec2(x, y));
-        draw_rectangle(pos.x, pos.y, GRIDSIZE as f32, GRIDSIZE as f32, color);
+        let size = GRIDSIZE as f32;
+        s.draw_rect(
+            game_to_world(ivec2(x, y)),
+            size,
+            size,
+            Z_DIJKSTRA_DEBUG,
+            color,
+        );
+        let pos: Vec2 = s.display_pos(game_to_world(ivec2(x, y))).into();
         // TODO
         if *val > 0 {
             let params = TextParams {
@@ -1069,10 +1757,27 @@ fn draw_dijkstra_map(s: &GameState, grid: &Grid<i32>) {
     }
 }
 
+/// plays the attack, then applies the result
+/// the defender may be gone afterwards
 async fn animate_attack(s: &mut CosyncInput<GameState>, e: ActorKey, enemy: (ActorKey, IVec2)) {
-    // insert attack animation here
+    let forecast = forecast_attack(&s.get().entities, e, enemy.0);
+    animate_lunge(s, e, enemy.1).await;
+    animate_damage(s, enemy.0, forecast.damage).await;
+
+    let s = &mut s.get();
+    s.entities[enemy.0].hp = forecast.defender_hp_after;
+    if forecast.defender_hp_after <= 0 {
+        // TODO animate death
+        s.entities.remove(enemy.0);
+        s.reach_cache.invalidate();
+    }
+
+    // TODO attack back if still alive
+}
+
+async fn animate_lunge(s: &mut CosyncInput<GameState>, e: ActorKey, target: IVec2) {
     let start = s.get().entities[e].draw_pos;
-    let target = game_to_world(enemy.1);
+    let target = game_to_world(target);
     let mut lerpiness = 0.;
     let speed = 5.;
     // forward
@@ -1095,61 +1800,54 @@ async fn animate_attack(s: &mut CosyncInput<GameState>, e: ActorKey, enemy: (Act
         }
         cosync::sleep_ticks(1).await;
     }
-
     s.get().entities[e].draw_pos = start;
-    let mut dmg = 5;
-    while dmg > 0 {
-        s.get().entities[enemy.0].hp -= 1;
-        dmg -= 1;
-        cosync::sleep_ticks(5).await;
-    }
+}
 
-    if s.get().entities[enemy.0].hp <= 0 {
-        // TODO animate death
-        s.get().entities.remove(enemy.0);
+/// counts the hp down one by one
+async fn animate_damage(s: &mut CosyncInput<GameState>, e: ActorKey, damage: i32) {
+    for _ in 0..damage {
+        {
+            let hp = &mut s.get().entities[e].hp;
+            *hp = (*hp - 1).max(0);
+        }
+        cosync::sleep_ticks(5).await;
     }
-
-    // TODO attack back if still alive
 }
 
-pub fn my_draw_texture(texture: &Texture2D, x: f32, y: f32, color: Color, src: Rect) {
-    let params = DrawTextureParams::default();
-    let context = unsafe { get_internal_gl() };
-
-    let [width, height] = texture.size().to_array();
-
-    let Rect {
-        x: sx,
-        y: sy,
-        w: sw,
-        h: sh,
-    } = src;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let (w, h) = match params.dest_size {
-        Some(dst) => (dst.x, dst.y),
-        _ => (sw, sh),
-    };
+    #[test]
+    fn one_confirm_only_opens_the_target_selection() {
+        let mut ui = UIState {
+            move_state: MoveState::Confirm,
+            menu_index: 1,
+            ..Default::default()
+        };
+        let mut keys = SlotMap::<ActorKey, ()>::with_key();
+        let enemies = [
+            (keys.insert(()), ivec2(2, 1)),
+            (keys.insert(()), ivec2(1, 2)),
+        ];
+        let mut confirm = true;
+        choose_attack(&mut ui, &enemies, &mut confirm);
+        assert_eq!(MoveState::ChooseAttack, ui.move_state);
+        assert_eq!(ivec2(2, 1), ui.grid_cursor);
+        // the target selection only attacks on the next press
+        assert!(!confirm);
+    }
 
-    let p = [
-        vec2(x, y),
-        vec2(x + w, y),
-        vec2(x + w, y + h),
-        vec2(x, y + h),
-    ];
-
-    // offsetting the uv slightly inward solves the problem of seams
-    // it only happened at some dpi in the web build
-    const OFFSET: f32 = 0.001;
-    #[rustfmt::skip]
-    let vertices = [
-        Vertex::new(p[0].x, p[0].y, 0.,  sx      /width +OFFSET,  sy      /height +OFFSET, color),
-        Vertex::new(p[1].x, p[1].y, 0., (sx + sw)/width -OFFSET,  sy      /height +OFFSET, color),
-        Vertex::new(p[2].x, p[2].y, 0., (sx + sw)/width -OFFSET, (sy + sh)/height -OFFSET, color),
-        Vertex::new(p[3].x, p[3].y, 0.,  sx      /width +OFFSET, (sy + sh)/height -OFFSET, color),
-    ];
-    let indices: [u16; 6] = [0, 1, 2, 0, 2, 3];
-
-    context.quad_gl.texture(Some(&texture));
-    context.quad_gl.draw_mode(DrawMode::Triangles);
-    context.quad_gl.geometry(&vertices, &indices);
+    #[test]
+    fn bad_arguments_are_errors() {
+        let args = |args: &str| args.split(' ').map(String::from).collect_vec();
+        assert_eq!(
+            Some(7),
+            GameArgs::parse(&args("--seed 7 --bot ./bot")).unwrap().seed
+        );
+        let e = GameArgs::parse(&args("--seed seven")).unwrap_err();
+        assert!(e.to_string().contains("bad seed 'seven'"), "{}", e);
+        let e = GameArgs::parse(&args("--map my.json --seed 7")).unwrap_err();
+        assert!(e.to_string().contains("--map and --seed"), "{}", e);
+    }
 }
@@ -0,0 +1,308 @@
+/// procedural skirmish maps from a seed
+/// the left half is generated and mirrored onto the right half,
+/// so both teams get the same ground, the same roads and the same units
+use crate::dijkstra::{dijkstra_map, UNREACHABLE};
+use crate::util::Rng;
+use crate::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MapGenConfig {
+    pub width: i32,
+    pub height: i32,
+    pub units_per_team: i32,
+    /// lakes on each half
+    pub lakes: i32,
+    /// forest clusters on each half
+    pub forests: i32,
+    /// a river running from top to bottom through the middle
+    pub river: bool,
+}
+
+impl Default for MapGenConfig {
+    fn default() -> Self {
+        Self {
+            width: 16,
+            height: 16,
+            units_per_team: 4,
+            lakes: 2,
+            forests: 3,
+            river: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneratedMap {
+    pub map: GameMap,
+    /// blue on the left, red on the right
+    pub hqs: [IVec2; 2],
+}
+
+/// maps which fail the fairness check are thrown away, this many times
+const MAX_ATTEMPTS: usize = 50;
+
+/// the same seed and config always give the same map
+pub fn generate(
+    seed: u64,
+    config: &MapGenConfig,
+    entity_defs: &HashMap<String, EntityDef>,
+) -> Result<GeneratedMap> {
+    if config.width < 6 || config.height < 3 {
+        anyhow::bail!("a skirmish map needs at least 6x3 tiles");
+    }
+    let mut rng = Rng::new(seed);
+    for _ in 0..MAX_ATTEMPTS {
+        let generated = generate_once(&mut rng, config, entity_defs)?;
+        if is_fair(&generated) {
+            return Ok(generated);
+        }
+    }
+    anyhow::bail!("no fair map after {} attempts", MAX_ATTEMPTS)
+}
+
+/// where `pos` ends up on the other half
+pub fn mirror(width: i32, pos: IVec2) -> IVec2 {
+    ivec2(width - 1 - pos.x, pos.y)
+}
+
+fn generate_once(
+    rng: &mut Rng,
+    config: &MapGenConfig,
+    entity_defs: &HashMap<String, EntityDef>,
+) -> Result<GeneratedMap> {
+    let (w, h) = (config.width, config.height);
+    let mut grids = Grids {
+        ground: Grid::new(w, h, GroundType::Ground),
+        terrain: Grid::new(w, h, TerrainType::None),
+        topology: Topology::default(),
+    };
+    let half = (w + 1) / 2;
+    // somewhere left of `max_x`
+    let random_pos = |rng: &mut Rng, max_x: i32| {
+        ivec2(
+            rng.below(max_x.clamp(1, half) as usize) as i32,
+            rng.below(h as usize) as i32,
+        )
+    };
+
+    let blue_hq = ivec2(
+        1 + rng.below(2) as i32,
+        1 + rng.below((h - 2) as usize) as i32,
+    );
+    let red_hq = mirror(w, blue_hq);
+
+    for _ in 0..config.lakes {
+        let radius = 1 + rng.below(2) as i32;
+        // lakes don't reach over to the other half, mirrored they'd merge
+        let center = random_pos(rng, half - radius - 1);
+        paint_blob(&mut grids.ground, center, radius, GroundType::Water);
+    }
+    if config.river {
+        // wanders sideways, but never further than the map's middle columns
+        let mut x = half - 1;
+        for y in 0..h {
+            // up to the middle, so the mirrored half joins it
+            for river_x in x..half {
+                grids.ground[(river_x, y)] = GroundType::Water;
+            }
+            x = (x + rng.below(3) as i32 - 1).clamp(half - 2, half - 1);
+        }
+    }
+    mirror_half(&mut grids.ground);
+
+    // the road between the bases crosses water at its narrowest
+    let road = road_between(&grids, blue_hq, red_hq);
+    for pos in road {
+        grids.ground[pos] = GroundType::Ground;
+        grids.terrain[pos] = TerrainType::Street;
+    }
+    for _ in 0..config.forests {
+        let center = random_pos(rng, half);
+        let radius = rng.below(2) as i32 + 1;
+        for pos in grids.terrain.coords() {
+            if pos.as_vec2().distance(center.as_vec2()) <= radius as f32
+                && grids.ground[pos] == GroundType::Ground
+                && grids.terrain[pos] == TerrainType::None
+                && rng.below(4) != 0
+            {
+                grids.terrain[pos] = TerrainType::Forest;
+            }
+        }
+    }
+    // the base itself is kept clear
+    for pos in std::iter::once(blue_hq).chain(grids.ground.neighbors(blue_hq, grids.topology)) {
+        grids.ground[pos] = GroundType::Ground;
+        if grids.terrain[pos] == TerrainType::Forest {
+            grids.terrain[pos] = TerrainType::None;
+        }
+    }
+    grids.terrain[blue_hq] = TerrainType::Street;
+    mirror_half(&mut grids.ground);
+    mirror_half(&mut grids.terrain);
+
+    let actors = place_units(rng, &grids, blue_hq, config.units_per_team, entity_defs)?;
+    Ok(GeneratedMap {
+        map: GameMap { grids, actors },
+        hqs: [blue_hq, red_hq],
+    })
+}
+
+fn paint_blob<T: Clone>(grid: &mut Grid<T>, center: IVec2, radius: i32, value: T) {
+    for pos in grid.coords() {
+        if pos.as_vec2().distance(center.as_vec2()) <= radius as f32 + 0.5 {
+            grid[pos] = value.clone();
+        }
+    }
+}
+
+/// copies the left half onto the right one
+fn mirror_half<T: Clone>(grid: &mut Grid<T>) {
+    for pos in grid.coords() {
+        let other = mirror(grid.width, pos);
+        if other.x < pos.x {
+            grid[pos] = grid[other].clone();
+        }
+    }
+}
+
+/// cheapest path for a road, going through water is possible but expensive
+fn road_between(grids: &Grids, from: IVec2, to: IVec2) -> Vec<IVec2> {
+    let (w, h) = (grids.ground.width, grids.ground.height);
+    let search = dijkstra_map(w, h, grids.topology, &[from], None, |pos| {
+        match grids.ground[pos] {
+            GroundType::Ground => 1,
+            GroundType::Water => 6,
+        }
+    });
+    search.path_to(to).unwrap_or_default()
+}
+
+/// units of the blue team around its hq, mirrored into their red counterparts
+fn place_units(
+    rng: &mut Rng,
+    grids: &Grids,
+    hq: IVec2,
+    count: i32,
+    entity_defs: &HashMap<String, EntityDef>,
+) -> Result<Vec<Actor>> {
+    let defs_of = |team| {
+        entity_defs
+            .iter()
+            .filter(|(_name, def)| def.team == team)
+            .sorted_by(|a, b| a.0.cmp(b.0))
+            .collect_vec()
+    };
+    let (blue, red) = (defs_of(Team::Blue), defs_of(Team::Red));
+    if blue.is_empty() {
+        anyhow::bail!("there are no blue units to place");
+    }
+
+    // closest free tiles first, ties in random order
+    let w = grids.ground.width;
+    let mut free = grids
+        .ground
+        .coords()
+        .into_iter()
+        .filter(|pos| {
+            *pos != hq && pos.x < mirror(w, *pos).x && grids.ground[*pos] == GroundType::Ground
+        })
+        .collect_vec();
+    rng.shuffle(&mut free);
+    free.sort_by_key(|pos| grids.topology.distance(*pos, hq));
+    if free.len() < count as usize {
+        anyhow::bail!("the map is too small for {} units per team", count);
+    }
+
+    let mut actors = vec![];
+    for pos in free.into_iter().take(count as usize) {
+        let (name, def) = blue[rng.below(blue.len())];
+        let (red_name, red_def) = red
+            .iter()
+            .find(|(_name, d)| d.unit_type == def.unit_type)
+            .ok_or_else(|| anyhow::anyhow!("no red counterpart of {}", name))?;
+        actors.push(Actor::from_def(name, def, pos));
+        actors.push(Actor::from_def(red_name, red_def, mirror(w, pos)));
+    }
+    Ok(actors)
+}
+
+/// both hqs can reach each other at the same cost,
+/// and each team's units are as far from the enemy hq as the other team's
+pub fn is_fair(generated: &GeneratedMap) -> bool {
+    let grids = &generated.map.grids;
+    let (w, h) = (grids.ground.width, grids.ground.height);
+    let no_units = SlotMap::with_key();
+    let distances = |from: IVec2, team: Team| {
+        let cost = movement_cost(grids, &no_units, team);
+        // water costs more than any real path, treat it as a wall
+        let search = dijkstra_map(w, h, grids.topology, &[from], Some(9998), cost);
+        search.cost
+    };
+    let [blue_hq, red_hq] = generated.hqs;
+    // how far the enemies of the team at `hq` have to go
+    let from_blue = distances(blue_hq, Team::Red);
+    let from_red = distances(red_hq, Team::Blue);
+    if from_blue[red_hq] == UNREACHABLE || from_blue[red_hq] != from_red[blue_hq] {
+        return false;
+    }
+    let approach = |team: Team, to: &Grid<i32>| {
+        generated
+            .map
+            .actors
+            .iter()
+            .filter(|a| a.team == team)
+            .map(|a| to[a.pos])
+            .sorted()
+            .collect_vec()
+    };
+    let blue = approach(Team::Blue, &from_red);
+    blue.iter().all(|d| *d != UNREACHABLE) && blue == approach(Team::Red, &from_blue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate_default(seed: u64) -> GeneratedMap {
+        generate(seed, &MapGenConfig::default(), &entity_defs().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn same_seed_same_map() {
+        let a = generate_default(3);
+        let b = generate_default(3);
+        assert_eq!(a.map.to_json(), b.map.to_json());
+        assert_ne!(a.map.to_json(), generate_default(4).map.to_json());
+    }
+
+    #[test]
+    fn maps_are_mirrored_and_fair() {
+        for seed in 0..20 {
+            let generated = generate_default(seed);
+            let grids = &generated.map.grids;
+            for (pos, ground) in grids.ground.iter_coords() {
+                let other = mirror(grids.ground.width, pos);
+                assert_eq!(*ground, grids.ground[other], "seed {} at {}", seed, pos);
+                assert_eq!(grids.terrain[pos], grids.terrain[other]);
+            }
+            let [blue_hq, red_hq] = generated.hqs;
+            assert_eq!(TerrainType::Street, grids.terrain[blue_hq]);
+            assert_eq!(TerrainType::Street, grids.terrain[red_hq]);
+            let actors = &generated.map.actors;
+            assert_eq!(8, actors.len());
+            assert_eq!(4, actors.iter().filter(|a| a.team == Team::Red).count());
+            assert!(is_fair(&generated), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn walled_off_bases_are_unfair() {
+        let mut generated = generate_default(1);
+        let grids = &mut generated.map.grids;
+        let x = grids.ground.width / 2;
+        for y in 0..grids.ground.height {
+            grids.ground[(x, y)] = GroundType::Water;
+        }
+        assert!(!is_fair(&generated));
+    }
+}
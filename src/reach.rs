@@ -0,0 +1,286 @@
+/// where a unit can go this turn and what it can attack from there
+/// shared by the player move preview, the ai and the rules
+use crate::ai::InfluenceMaps;
+use crate::*;
+use std::sync::Arc;
+
+pub struct Reachability {
+    pub unit: ActorKey,
+    /// cheapest paths to every tile within `MOVE_POINTS`
+    /// allies can be passed, enemies block
+    pub moves: DijkstraMap,
+    /// reachable tiles that no other unit stands on
+    pub stoppable: Grid<bool>,
+    /// enemies in attack range for every stoppable tile that has any
+    attacks: HashMap<IVec2, Vec<(ActorKey, IVec2)>>,
+}
+
+impl Reachability {
+    pub fn new(grids: &Grids, entities: &SlotMap<ActorKey, Actor>, me: ActorKey) -> Self {
+        let actor = &entities[me];
+        let (w, h) = (grids.ground.width, grids.ground.height);
+        let moves = dijkstra_map(
+            w,
+            h,
+            grids.topology,
+            &[actor.pos],
+            Some(MOVE_POINTS),
+            movement_cost(grids, entities, actor.team),
+        );
+        let mut stoppable = Grid::filled_with(w, h, |x, y| moves.is_reached(ivec2(x, y)));
+        for (index, other) in entities.iter() {
+            if index != me && stoppable.is_valid(other.pos) {
+                stoppable[other.pos] = false;
+            }
+        }
+        let mut attacks = HashMap::new();
+        for (pos, can_stop) in stoppable.iter_coords() {
+            if !*can_stop {
+                continue;
+            }
+            let enemies = enemies_in_range_from(grids, entities, me, pos);
+            if !enemies.is_empty() {
+                attacks.insert(pos, enemies);
+            }
+        }
+        Self {
+            unit: me,
+            moves,
+            stoppable,
+            attacks,
+        }
+    }
+
+    /// the unit can move through the tile, but maybe not stop on it
+    pub fn is_reachable(&self, pos: IVec2) -> bool {
+        self.moves.cost.is_valid(pos) && self.moves.is_reached(pos)
+    }
+
+    pub fn can_stop(&self, pos: IVec2) -> bool {
+        self.stoppable.is_valid(pos) && self.stoppable[pos]
+    }
+
+    /// movement cost to get to `pos`, None if not reachable
+    pub fn cost(&self, pos: IVec2) -> Option<i32> {
+        self.is_reachable(pos).then(|| self.moves.cost[pos])
+    }
+
+    /// cheapest path from the unit to `pos`, both inclusive
+    /// None if the unit can't stop there
+    pub fn path_to(&self, pos: IVec2) -> Option<Vec<IVec2>> {
+        if !self.can_stop(pos) {
+            return None;
+        }
+        self.moves.path_to(pos)
+    }
+
+    pub fn reachable_tiles(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.moves
+            .cost
+            .iter_coords()
+            .filter(|(pos, _)| self.moves.is_reached(*pos))
+            .map(|(pos, _)| pos)
+    }
+
+    pub fn stoppable_tiles(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.stoppable
+            .iter_coords()
+            .filter(|(_, can_stop)| **can_stop)
+            .map(|(pos, _)| pos)
+    }
+
+    /// enemies the unit could attack after moving to `pos`
+    pub fn attacks_from(&self, pos: IVec2) -> &[(ActorKey, IVec2)] {
+        self.attacks
+            .get(&pos)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// whether any enemy can be attacked from any stoppable tile
+    pub fn has_targets(&self) -> bool {
+        !self.attacks.is_empty()
+    }
+
+    /// every combination of stoppable tile and attack from there
+    pub fn actions(&self) -> Vec<UnitAction> {
+        let mut actions = Vec::new();
+        for pos in self.stoppable_tiles() {
+            actions.push(UnitAction {
+                move_to: pos,
+                attack: None,
+            });
+            for (_enemy, enemy_pos) in self.attacks_from(pos) {
+                actions.push(UnitAction {
+                    move_to: pos,
+                    attack: Some(*enemy_pos),
+                });
+            }
+        }
+        actions
+    }
+}
+
+/// remembers the reachability of units and the influence maps between frames
+/// whoever changes the grids or moves, adds or removes units has to call `invalidate`
+/// hp and draw positions don't matter, so animations don't invalidate the cache
+#[derive(Default)]
+pub struct ReachCache {
+    entries: HashMap<ActorKey, Arc<Reachability>>,
+    influence: Option<Arc<InfluenceMaps>>,
+}
+
+impl ReachCache {
+    pub fn get(
+        &mut self,
+        grids: &Grids,
+        entities: &SlotMap<ActorKey, Actor>,
+        unit: ActorKey,
+    ) -> Arc<Reachability> {
+        self.entries
+            .entry(unit)
+            .or_insert_with(|| Arc::new(Reachability::new(grids, entities, unit)))
+            .clone()
+    }
+
+    pub fn influence(
+        &mut self,
+        grids: &Grids,
+        entities: &SlotMap<ActorKey, Actor>,
+    ) -> Arc<InfluenceMaps> {
+        self.influence
+            .get_or_insert_with(|| Arc::new(InfluenceMaps::new(grids, entities)))
+            .clone()
+    }
+
+    /// the board changed, everything has to be computed again
+    pub fn invalidate(&mut self) {
+        self.entries.clear();
+        self.influence = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ascii_map::test_board;
+
+    /// `u` is the unit that moves, always in the top left corner
+    fn board(rows: &str) -> (Grids, SlotMap<ActorKey, Actor>, ActorKey) {
+        let legend = "u: blue_infantry\na: blue_infantry\ne: red_infantry";
+        let (grids, entities) = test_board(&format!("{}\n\n{}", rows, legend));
+        let me = actor_at_pos(&entities, ivec2(0, 0)).unwrap();
+        (grids, entities, me)
+    }
+
+    #[test]
+    fn streets_and_water() {
+        let (grids, entities, me) = board(
+            "
+            u=========
+            ~~~~~~~~~.
+            ..........
+            ",
+        );
+        let reach = Reachability::new(&grids, &entities, me);
+        assert_eq!(Some(8), reach.cost(ivec2(8, 0)));
+        assert!(!reach.is_reachable(ivec2(9, 0)));
+        assert!(!reach.is_reachable(ivec2(0, 1)));
+        assert!(!reach.is_reachable(ivec2(0, 2)));
+        assert_eq!(9, reach.reachable_tiles().count());
+        assert_eq!(9, reach.path_to(ivec2(8, 0)).unwrap().len());
+    }
+
+    #[test]
+    fn allies_are_passed_but_not_stopped_on() {
+        let (grids, entities, me) = board(
+            "
+            ua==
+            ~~~~
+            ",
+        );
+        let reach = Reachability::new(&grids, &entities, me);
+        assert!(reach.is_reachable(ivec2(1, 0)));
+        assert!(!reach.can_stop(ivec2(1, 0)));
+        assert_eq!(None, reach.path_to(ivec2(1, 0)));
+        assert_eq!(
+            vec![ivec2(0, 0), ivec2(1, 0), ivec2(2, 0), ivec2(3, 0)],
+            reach.path_to(ivec2(3, 0)).unwrap()
+        );
+        // staying is always possible
+        assert!(reach.can_stop(ivec2(0, 0)));
+    }
+
+    #[test]
+    fn enemies_block_and_can_be_attacked() {
+        let (grids, entities, me) = board(
+            "
+            ue==
+            .~~~
+            ....
+            ",
+        );
+        let reach = Reachability::new(&grids, &entities, me);
+        assert!(!reach.is_reachable(ivec2(1, 0)));
+        assert!(!reach.is_reachable(ivec2(2, 0)));
+
+        let enemy = actor_at_pos(&entities, ivec2(1, 0)).unwrap();
+        assert_eq!(&[(enemy, ivec2(1, 0))], reach.attacks_from(ivec2(0, 0)));
+        assert!(reach.attacks_from(ivec2(0, 1)).is_empty());
+
+        let actions = reach.actions();
+        assert!(actions.contains(&UnitAction {
+            move_to: ivec2(0, 0),
+            attack: Some(ivec2(1, 0)),
+        }));
+        assert_eq!(
+            reach.stoppable_tiles().count() + 1,
+            actions.len(),
+            "one attack on top of the plain moves"
+        );
+    }
+
+    #[test]
+    fn cache_is_kept_until_invalidated() {
+        let (mut grids, mut entities, me) = board(
+            "
+            u...
+            ....
+            ",
+        );
+        let mut cache = ReachCache::default();
+        let first = cache.get(&grids, &entities, me);
+        assert!(Arc::ptr_eq(&first, &cache.get(&grids, &entities, me)));
+        let influence = cache.influence(&grids, &entities);
+        assert!(Arc::ptr_eq(&influence, &cache.influence(&grids, &entities)));
+
+        entities[me].draw_pos = vec2(100., 100.);
+        entities[me].hp -= 1;
+        assert!(Arc::ptr_eq(&first, &cache.get(&grids, &entities, me)));
+
+        entities[me].pos = ivec2(3, 1);
+        cache.invalidate();
+        let moved = cache.get(&grids, &entities, me);
+        assert!(!Arc::ptr_eq(&first, &moved));
+        assert!(!Arc::ptr_eq(
+            &influence,
+            &cache.influence(&grids, &entities)
+        ));
+        assert_eq!(Some(0), moved.cost(ivec2(3, 1)));
+
+        grids.ground[(2, 1)] = GroundType::Water;
+        cache.invalidate();
+        let flooded = cache.get(&grids, &entities, me);
+        assert!(!Arc::ptr_eq(&moved, &flooded));
+        assert!(!flooded.is_reachable(ivec2(2, 1)));
+    }
+
+    #[test]
+    fn forest_costs_more() {
+        let (grids, entities, me) = board("u^^^.");
+        let reach = Reachability::new(&grids, &entities, me);
+        assert_eq!(Some(6), reach.cost(ivec2(2, 0)));
+        assert!(!reach.is_reachable(ivec2(3, 0)));
+    }
+}
@@ -0,0 +1,247 @@
+/// `comfy-wars render`: draws a map into a png on the cpu, no window or gpu needed
+/// for map thumbnails, documentation and golden image tests on ci
+/// uses the same tilemap and sprite rects as the game
+use crate::autotile::{self, AutoTile};
+use crate::mapgen::MapGenConfig;
+use crate::*;
+use image::{Rgba, RgbaImage};
+
+const USAGE: &str = "usage: comfy-wars render [--map <file>] [--level <index>] \
+[--seed <n>] [--scale <n>] [--out <file.png>]";
+
+#[derive(Debug)]
+pub struct RenderArgs {
+    /// an ldtk file, a map saved by the editor or a text map
+    pub map: String,
+    /// level of an ldtk file
+    pub level: usize,
+    /// renders a generated skirmish map instead of `map`
+    pub seed: Option<u64>,
+    /// every pixel of the tilemap becomes a square of this size
+    pub scale: u32,
+    pub out: String,
+}
+
+impl Default for RenderArgs {
+    fn default() -> Self {
+        Self {
+            map: "assets/comfy_wars.ldtk".to_string(),
+            level: 0,
+            seed: None,
+            scale: 1,
+            out: "map.png".to_string(),
+        }
+    }
+}
+
+impl RenderArgs {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut result = Self::default();
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .ok_or_else(|| anyhow::anyhow!("missing value for {}\n{}", flag, USAGE))
+            };
+            match flag.as_str() {
+                "--map" => result.map = value()?.clone(),
+                "--level" => result.level = value()?.parse()?,
+                "--seed" => result.seed = Some(value()?.parse()?),
+                "--scale" => result.scale = value()?.parse()?,
+                "--out" => result.out = value()?.clone(),
+                _ => anyhow::bail!("unknown argument {}\n{}", flag, USAGE),
+            }
+        }
+        if result.scale == 0 {
+            anyhow::bail!("scale has to be at least 1");
+        }
+        Ok(result)
+    }
+}
+
+/// everything that ends up in the picture
+pub struct MapLayers {
+    pub map: GameMap,
+    pub ground: Vec<AutoTile>,
+    pub terrain: Vec<AutoTile>,
+}
+
+impl MapLayers {
+    /// ldtk levels come with their own tiles, everything else is autotiled
+    pub fn load(path: &str, level: usize) -> Result<Self> {
+        if !path.ends_with(".ldtk") {
+            return Ok(Self::autotiled(GameMap::load(path)?));
+        }
+        let ldtk: LDTK = DeJson::deserialize_json(&std::fs::read_to_string(path)?)?;
+        let level = ldtk
+            .levels
+            .get(level)
+            .ok_or_else(|| anyhow::anyhow!("map has no level {}", level))?;
+        let baked = |id: &str| {
+            level
+                .layers
+                .iter()
+                .filter(|layer| layer.id == id)
+                .flat_map(|layer| &layer.auto_tiles)
+                .map(|tile| AutoTile {
+                    pos: ivec2(tile.px[0] as i32, tile.px[1] as i32) / GRIDSIZE,
+                    src: ivec2(tile.src[0], tile.src[1]),
+                })
+                .collect_vec()
+        };
+        Ok(Self {
+            ground: baked("groundgrid"),
+            terrain: baked("infrastructuregrid"),
+            map: GameMap::from_level(level, &entity_defs()?)?,
+        })
+    }
+
+    pub fn autotiled(map: GameMap) -> Self {
+        Self {
+            ground: autotile::ground_tiles(&map.grids.ground),
+            terrain: autotile::terrain_tiles(&map.grids.terrain),
+            map,
+        }
+    }
+}
+
+/// ground, then terrain, then units, each blended over what is below
+pub fn render(
+    layers: &MapLayers,
+    entity_defs: &HashMap<String, EntityDef>,
+    tilemap: &RgbaImage,
+    scale: u32,
+) -> RgbaImage {
+    let grids = &layers.map.grids;
+    let tile = GRIDSIZE as u32 * scale;
+    let mut image = RgbaImage::new(
+        grids.ground.width as u32 * tile,
+        grids.ground.height as u32 * tile,
+    );
+    let units = layers.map.actors.iter().filter_map(|actor| {
+        let sprite = &entity_defs.get(&actor.sprite_name)?.sprite;
+        Some(AutoTile {
+            pos: actor.pos,
+            src: ivec2(sprite.x, sprite.y),
+        })
+    });
+    for sprite in layers
+        .ground
+        .iter()
+        .chain(&layers.terrain)
+        .copied()
+        .chain(units)
+    {
+        blit(&mut image, tilemap, sprite, scale);
+    }
+    image
+}
+
+/// draws one sprite of the tilemap onto its tile
+fn blit(image: &mut RgbaImage, tilemap: &RgbaImage, sprite: AutoTile, scale: u32) {
+    let size = GRIDSIZE as u32;
+    for y in 0..size * scale {
+        for x in 0..size * scale {
+            let (sx, sy) = (
+                sprite.src.x as u32 + x / scale,
+                sprite.src.y as u32 + y / scale,
+            );
+            let (dx, dy) = (
+                sprite.pos.x as u32 * size * scale + x,
+                sprite.pos.y as u32 * size * scale + y,
+            );
+            if sx < tilemap.width()
+                && dx < image.width()
+                && sy < tilemap.height()
+                && dy < image.height()
+            {
+                let below = *image.get_pixel(dx, dy);
+                image.put_pixel(dx, dy, blend(*tilemap.get_pixel(sx, sy), below));
+            }
+        }
+    }
+}
+
+/// `over` drawn on top of `below`, straight alpha
+fn blend(over: Rgba<u8>, below: Rgba<u8>) -> Rgba<u8> {
+    let a = over[3] as u32;
+    let b = below[3] as u32 * (255 - a) / 255;
+    let alpha = a + b;
+    if alpha == 0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let mut result = [0; 4];
+    for i in 0..3 {
+        result[i] = ((over[i] as u32 * a + below[i] as u32 * b) / alpha) as u8;
+    }
+    result[3] = alpha as u8;
+    Rgba(result)
+}
+
+pub fn load_tilemap() -> Result<RgbaImage> {
+    let png = kf_include_bytes!("/assets/tilemap/tilemap_packed.png");
+    Ok(image::load_from_memory(png)?.to_rgba8())
+}
+
+/// entry point of the subcommand
+pub fn render_main(args: &[String]) -> Result<()> {
+    let args = RenderArgs::parse(args)?;
+    let entity_defs = entity_defs()?;
+    let layers = match args.seed {
+        Some(seed) => {
+            let generated = mapgen::generate(seed, &MapGenConfig::default(), &entity_defs)?;
+            MapLayers::autotiled(generated.map)
+        }
+        None => MapLayers::load(&args.map, args.level)?,
+    };
+    let image = render(&layers, &entity_defs, &load_tilemap()?, args.scale);
+    image.save(&args.out)?;
+    println!(
+        "rendered {}x{} pixels to {}",
+        image.width(),
+        image.height(),
+        args.out
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blending() {
+        let red = Rgba([255, 0, 0, 255]);
+        let clear = Rgba([0, 0, 255, 0]);
+        assert_eq!(red, blend(red, Rgba([0, 255, 0, 255])));
+        assert_eq!(red, blend(clear, red));
+        assert_eq!(Rgba([127, 0, 128, 255]), blend(Rgba([0, 0, 255, 128]), red));
+    }
+
+    /// regenerate after intended changes with
+    /// `cargo run -- render --out assets/golden/builtin_map.png`
+    #[test]
+    fn builtin_map_matches_golden_image() {
+        let args = RenderArgs::default();
+        let layers = MapLayers::load(&args.map, args.level).unwrap();
+        let tilemap = load_tilemap().unwrap();
+        let image = render(&layers, &entity_defs().unwrap(), &tilemap, args.scale);
+        let golden = image::open("assets/golden/builtin_map.png")
+            .unwrap()
+            .to_rgba8();
+        if image != golden {
+            let actual = "target/builtin_map.actual.png";
+            image.save(actual).unwrap();
+            panic!("the builtin map renders differently now, see {}", actual);
+        }
+    }
+
+    #[test]
+    fn parse_args() {
+        let args = ["--seed", "3", "--scale", "4", "--out", "thumb.png"].map(String::from);
+        let parsed = RenderArgs::parse(&args).unwrap();
+        assert_eq!((Some(3), 4), (parsed.seed, parsed.scale));
+        assert_eq!("thumb.png", parsed.out);
+        assert!(RenderArgs::parse(&["--scale".to_string(), "0".to_string()]).is_err());
+    }
+}
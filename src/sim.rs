@@ -0,0 +1,183 @@
+/// headless matches between two ai controlled teams
+/// no window, no animations, everything resolves instantly
+use crate::ai::*;
+use crate::util::Rng;
+use crate::*;
+
+pub struct Match {
+    pub grids: Grids,
+    pub entities: SlotMap<ActorKey, Actor>,
+    /// full rounds played, a round is over once both teams had their turn
+    pub round: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchResult {
+    /// None if the round limit was hit
+    pub winner: Option<Team>,
+    pub rounds: i32,
+    pub blue_losses: i32,
+    pub red_losses: i32,
+}
+
+impl Match {
+    pub fn new(grids: Grids, actors: impl IntoIterator<Item = Actor>) -> Self {
+        let mut entities = SlotMap::with_key();
+        for actor in actors {
+            entities.insert(actor);
+        }
+        Self {
+            grids,
+            entities,
+            round: 0,
+        }
+    }
+
+    pub fn unit_count(&self, team: Team) -> i32 {
+        self.entities.values().filter(|a| a.team == team).count() as i32
+    }
+
+    /// the last team standing
+    pub fn winner(&self) -> Option<Team> {
+        match (self.unit_count(Team::Blue), self.unit_count(Team::Red)) {
+            (0, 0) => None,
+            (_, 0) => Some(Team::Blue),
+            (0, _) => Some(Team::Red),
+            _ => None,
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.winner().is_some()
+    }
+
+    /// lets every unit of the team move and attack once
+    /// units act in a random order
+    pub fn play_turn(&mut self, team: Team, controller: &mut Controller, rng: &mut Rng) {
+        let mut units = self
+            .entities
+            .iter()
+            .filter(|(_k, a)| a.team == team)
+            .map(|(k, _a)| k)
+            .collect_vec();
+        rng.shuffle(&mut units);
+
+        for index in units {
+            // may have died in the meantime
+            if !self.entities.contains_key(index) {
+                continue;
+            }
+            let action = controller.decide(&self.grids, &self.entities, index);
+            self.apply(index, action);
+            if self.is_over() {
+                return;
+            }
+        }
+
+        for actor in self.entities.values_mut() {
+            actor.has_moved = false;
+        }
+    }
+
+    /// executes an action, which has to be one of the `legal_actions`
+    pub fn apply(&mut self, index: ActorKey, action: UnitAction) {
+        let actor = &mut self.entities[index];
+        actor.pos = action.move_to;
+        actor.draw_pos = game_to_world(action.move_to);
+        actor.has_moved = true;
+
+        if let Some(enemy) = action
+            .attack
+            .and_then(|pos| actor_at_pos(&self.entities, pos))
+        {
+            resolve_attack(&mut self.entities, index, enemy);
+        }
+    }
+
+    /// plays until one team is wiped out or the round limit is hit
+    pub fn run(
+        mut self,
+        blue: &mut Controller,
+        red: &mut Controller,
+        rng: &mut Rng,
+        max_rounds: i32,
+    ) -> MatchResult {
+        let blue_start = self.unit_count(Team::Blue);
+        let red_start = self.unit_count(Team::Red);
+        let first = if rng.below(2) == 0 {
+            Team::Blue
+        } else {
+            Team::Red
+        };
+
+        while !self.is_over() && self.round < max_rounds {
+            self.round += 1;
+            for team in [first, first.other()] {
+                let controller = match team {
+                    Team::Blue => &mut *blue,
+                    Team::Red => &mut *red,
+                };
+                self.play_turn(team, controller, rng);
+                if self.is_over() {
+                    break;
+                }
+            }
+        }
+
+        MatchResult {
+            winner: self.winner(),
+            rounds: self.round,
+            blue_losses: blue_start - self.unit_count(Team::Blue),
+            red_losses: red_start - self.unit_count(Team::Red),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// infantry on streets
+    fn street_map(rows: &str) -> GameMap {
+        let legend = "b: blue_infantry on==\nr: red_infantry on==";
+        GameMap::from_ascii(&format!("{}\n\n{}", rows, legend), &entity_defs().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn outnumbered_team_loses() {
+        let map = street_map(
+            "
+            ========
+            ========
+            =b====r=
+            =b======
+            ========",
+        );
+        let m = Match::new(map.grids, map.actors);
+        let (mut blue, mut red) = (Controller::default(), Controller::default());
+        let result = m.run(&mut blue, &mut red, &mut Rng::new(1), 50);
+        assert_eq!(Some(Team::Blue), result.winner);
+        assert_eq!(1, result.red_losses);
+        assert!(result.rounds > 0);
+    }
+
+    #[test]
+    fn same_seed_same_result() {
+        let map = street_map(
+            "
+            ========
+            =b====r=
+            ========
+            =b====r=
+            ========",
+        );
+        let run = |blue: &mut Controller, red: &mut Controller| {
+            let m = Match::new(map.grids.clone(), map.actors.iter().cloned());
+            m.run(blue, red, &mut Rng::new(7), 50)
+        };
+        let (mut blue, mut red) = (Controller::default(), Controller::default());
+        let a = run(&mut blue, &mut red);
+        let b = run(&mut blue, &mut red);
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,171 @@
+/// how the tiles of a grid connect to each other
+/// pathfinding, attack range and drawing all go through this
+use crate::grids::Grid;
+use macroquad::math::{ivec2, vec2, IVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Topology {
+    /// 4 neighbors
+    #[default]
+    Orthogonal,
+    /// 8 neighbors, diagonal steps cost one and a half times as much
+    Octile,
+    /// hex tiles in "odd-r" offset coordinates,
+    /// odd rows are shifted right by half a tile
+    Hex,
+}
+
+const ORTHOGONAL: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, 1), (0, -1)];
+const OCTILE: [(i32, i32); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, 1),
+    (0, -1),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+    (1, 1),
+];
+const HEX_EVEN_ROW: [(i32, i32); 6] = [(-1, 0), (1, 0), (-1, -1), (0, -1), (-1, 1), (0, 1)];
+const HEX_ODD_ROW: [(i32, i32); 6] = [(-1, 0), (1, 0), (0, -1), (1, -1), (0, 1), (1, 1)];
+
+impl Topology {
+    pub const ALL: [Topology; 3] = [Topology::Orthogonal, Topology::Octile, Topology::Hex];
+
+    /// offsets from `pos` to its neighbors, for hex maps they depend on the row
+    pub fn offsets(self, pos: IVec2) -> &'static [(i32, i32)] {
+        match self {
+            Topology::Orthogonal => &ORTHOGONAL,
+            Topology::Octile => &OCTILE,
+            Topology::Hex if pos.y.rem_euclid(2) == 0 => &HEX_EVEN_ROW,
+            Topology::Hex => &HEX_ODD_ROW,
+        }
+    }
+
+    /// cost of stepping from `from` onto its neighbor `to`,
+    /// given the cost of entering `to`
+    pub fn step_cost(self, from: IVec2, to: IVec2, cost: i32) -> i32 {
+        let diagonal = from.x != to.x && from.y != to.y;
+        if self == Topology::Octile && diagonal {
+            // 1.5 times the cost, rounded up: 1 -> 2, 2 -> 3, 3 -> 5
+            cost.saturating_mul(3).saturating_add(1) / 2
+        } else {
+            cost
+        }
+    }
+
+    /// number of steps between two tiles
+    pub fn distance(self, a: IVec2, b: IVec2) -> i32 {
+        let d = (a - b).abs();
+        match self {
+            Topology::Orthogonal => d.x + d.y,
+            Topology::Octile => d.x.max(d.y),
+            Topology::Hex => {
+                let (a, b) = (hex_axial(a), hex_axial(b));
+                let (dq, dr) = (a.x - b.x, a.y - b.y);
+                (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+            }
+        }
+    }
+
+    /// lower bound for the cost of a path between two tiles
+    /// when every tile costs at least 1 to enter
+    pub fn min_cost(self, a: IVec2, b: IVec2) -> i32 {
+        match self {
+            // a diagonal costs at least 2, same as going around the corner
+            Topology::Octile => Topology::Orthogonal.distance(a, b),
+            _ => self.distance(a, b),
+        }
+    }
+
+    /// where a tile at world position `pos` is actually drawn
+    /// in between rows the shift is interpolated, so moving units don't jump
+    pub fn display_pos(self, pos: Vec2, tile_size: f32) -> Vec2 {
+        match self {
+            Topology::Hex => {
+                let row = pos.y / tile_size;
+                // 0 on even rows, 1 on odd rows
+                let odd = 1. - ((row.rem_euclid(2.)) - 1.).abs();
+                vec2(pos.x + odd * tile_size / 2., pos.y)
+            }
+            _ => pos,
+        }
+    }
+
+    /// inverse of `display_pos`
+    pub fn world_pos(self, display_pos: Vec2, tile_size: f32) -> Vec2 {
+        // rows are never shifted vertically, so the offset can be recomputed
+        display_pos - (self.display_pos(display_pos, tile_size) - display_pos)
+    }
+}
+
+/// odd-r offset coordinates to axial ones
+fn hex_axial(pos: IVec2) -> IVec2 {
+    ivec2(pos.x - (pos.y - (pos.y & 1)) / 2, pos.y)
+}
+
+impl<T> Grid<T> {
+    /// neighbors of `pos` that lie inside the grid
+    pub fn neighbors(&self, pos: IVec2, topology: Topology) -> Vec<IVec2> {
+        topology
+            .offsets(pos)
+            .iter()
+            .map(|(dx, dy)| ivec2(pos.x + dx, pos.y + dy))
+            .filter(|p| 0 <= p.x && p.x < self.width && 0 <= p.y && p.y < self.height)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbor_counts() {
+        let grid = Grid::new(5, 5, 0);
+        let center = ivec2(2, 2);
+        assert_eq!(4, grid.neighbors(center, Topology::Orthogonal).len());
+        assert_eq!(8, grid.neighbors(center, Topology::Octile).len());
+        assert_eq!(6, grid.neighbors(center, Topology::Hex).len());
+        assert_eq!(6, grid.neighbors(ivec2(2, 1), Topology::Hex).len());
+        assert_eq!(3, grid.neighbors(ivec2(0, 0), Topology::Octile).len());
+        assert_eq!(2, grid.neighbors(ivec2(0, 0), Topology::Hex).len());
+    }
+
+    #[test]
+    fn hex_neighbors_are_at_distance_one() {
+        for pos in [ivec2(3, 2), ivec2(3, 3)] {
+            for (dx, dy) in Topology::Hex.offsets(pos) {
+                let n = pos + ivec2(*dx, *dy);
+                assert_eq!(1, Topology::Hex.distance(pos, n), "{} -> {}", pos, n);
+                // neighborhood is symmetric
+                assert!(Topology::Hex
+                    .offsets(n)
+                    .iter()
+                    .any(|(dx, dy)| n + ivec2(*dx, *dy) == pos));
+            }
+        }
+        assert_eq!(3, Topology::Hex.distance(ivec2(0, 0), ivec2(0, 3)));
+        assert_eq!(4, Topology::Hex.distance(ivec2(0, 0), ivec2(3, 2)));
+    }
+
+    #[test]
+    fn diagonal_costs_more() {
+        let t = Topology::Octile;
+        assert_eq!(2, t.step_cost(ivec2(0, 0), ivec2(1, 0), 2));
+        assert_eq!(3, t.step_cost(ivec2(0, 0), ivec2(1, 1), 2));
+        assert_eq!(2, t.step_cost(ivec2(0, 0), ivec2(1, 1), 1));
+        assert_eq!(1, Topology::Hex.step_cost(ivec2(0, 0), ivec2(0, 1), 1));
+    }
+
+    #[test]
+    fn display_pos_round_trip() {
+        let t = Topology::Hex;
+        assert_eq!(vec2(0., 0.), t.display_pos(vec2(0., 0.), 16.));
+        assert_eq!(vec2(8., 16.), t.display_pos(vec2(0., 16.), 16.));
+        assert_eq!(vec2(4., 8.), t.display_pos(vec2(0., 8.), 16.));
+        let pos = vec2(37., 21.);
+        assert_eq!(pos, t.world_pos(t.display_pos(pos, 16.), 16.));
+    }
+}
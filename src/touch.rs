@@ -0,0 +1,195 @@
+/// turns raw touches into the gestures the game understands
+/// tap is a left click, a long press a right click,
+/// dragging one finger pans and pinching two fingers zooms
+use macroquad::input::{Touch, TouchPhase};
+use macroquad::math::Vec2;
+
+/// pixels a finger may wander before a tap becomes a drag
+const DRAG_THRESHOLD: f32 = 12.;
+/// seconds a finger has to rest for a long press
+const LONG_PRESS_TIME: f64 = 0.5;
+/// change in finger distance for one zoom step
+const PINCH_STEP: f32 = 1.5;
+
+/// what happened this frame, all positions in screen pixels
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Gestures {
+    pub tap: Option<Vec2>,
+    pub long_press: Option<Vec2>,
+    /// previous and current position of a dragging finger
+    pub pan: Option<(Vec2, Vec2)>,
+    /// zoom steps, like the mouse wheel
+    pub zoom: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PressState {
+    /// may still become a tap
+    Pending,
+    Dragging,
+    /// done, ending it does nothing
+    Used,
+}
+
+#[derive(Debug, Clone)]
+struct Press {
+    id: u64,
+    start: Vec2,
+    last: Vec2,
+    time: f64,
+    state: PressState,
+}
+
+#[derive(Debug, Default)]
+pub struct TouchInput {
+    /// the finger that went down first
+    press: Option<Press>,
+    /// finger distance at the last zoom step of a pinch
+    pinch_base: Option<f32>,
+}
+
+impl TouchInput {
+    /// call once per frame with the touches of that frame
+    pub fn update(&mut self, touches: &[Touch], time: f64) -> Gestures {
+        let mut gestures = Gestures::default();
+        let down = touches
+            .iter()
+            .filter(|t| !matches!(t.phase, TouchPhase::Ended | TouchPhase::Cancelled))
+            .collect::<Vec<_>>();
+
+        if let [a, b, ..] = down[..] {
+            // a pinch never turns into a tap or drag
+            if let Some(press) = &mut self.press {
+                press.state = PressState::Used;
+            }
+            let distance = a.position.distance(b.position);
+            let base = *self.pinch_base.get_or_insert(distance);
+            if distance >= base * PINCH_STEP {
+                gestures.zoom = 1;
+                self.pinch_base = Some(distance);
+            } else if distance <= base / PINCH_STEP {
+                gestures.zoom = -1;
+                self.pinch_base = Some(distance);
+            }
+        } else {
+            self.pinch_base = None;
+        }
+
+        for touch in touches {
+            let pos = touch.position;
+            match (&mut self.press, touch.phase) {
+                (None, TouchPhase::Started) if down.len() == 1 => {
+                    self.press = Some(Press {
+                        id: touch.id,
+                        start: pos,
+                        last: pos,
+                        time,
+                        state: PressState::Pending,
+                    });
+                }
+                (None, TouchPhase::Ended) if down.is_empty() => {
+                    // started and ended within a single frame
+                    gestures.tap = Some(pos);
+                }
+                (Some(press), TouchPhase::Ended) if press.id == touch.id => {
+                    if press.state == PressState::Pending {
+                        gestures.tap = Some(pos);
+                    }
+                    self.press = None;
+                }
+                (Some(press), TouchPhase::Cancelled) if press.id == touch.id => {
+                    self.press = None;
+                }
+                (Some(press), TouchPhase::Moved | TouchPhase::Stationary)
+                    if press.id == touch.id =>
+                {
+                    if press.state == PressState::Pending {
+                        if press.start.distance(pos) > DRAG_THRESHOLD {
+                            press.state = PressState::Dragging;
+                        } else if time - press.time >= LONG_PRESS_TIME {
+                            press.state = PressState::Used;
+                            gestures.long_press = Some(press.start);
+                        }
+                    }
+                    if press.state == PressState::Dragging && down.len() == 1 {
+                        gestures.pan = Some((press.last, pos));
+                    }
+                    press.last = pos;
+                }
+                _ => {}
+            }
+        }
+        gestures
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macroquad::math::vec2;
+
+    fn touch(id: u64, phase: TouchPhase, x: f32, y: f32) -> Touch {
+        Touch {
+            id,
+            phase,
+            position: vec2(x, y),
+        }
+    }
+
+    #[test]
+    fn tap_and_drag() {
+        let mut input = TouchInput::default();
+        input.update(&[touch(1, TouchPhase::Started, 10., 10.)], 0.);
+        input.update(&[touch(1, TouchPhase::Moved, 14., 10.)], 0.1);
+        let gestures = input.update(&[touch(1, TouchPhase::Ended, 14., 10.)], 0.2);
+        assert_eq!(Some(vec2(14., 10.)), gestures.tap);
+
+        input.update(&[touch(2, TouchPhase::Started, 10., 10.)], 1.);
+        let gestures = input.update(&[touch(2, TouchPhase::Moved, 50., 10.)], 1.1);
+        assert_eq!(Some((vec2(10., 10.), vec2(50., 10.))), gestures.pan);
+        let gestures = input.update(&[touch(2, TouchPhase::Moved, 60., 20.)], 1.2);
+        assert_eq!(Some((vec2(50., 10.), vec2(60., 20.))), gestures.pan);
+        let gestures = input.update(&[touch(2, TouchPhase::Ended, 60., 20.)], 1.3);
+        assert_eq!(Gestures::default(), gestures);
+    }
+
+    #[test]
+    fn long_press_once() {
+        let mut input = TouchInput::default();
+        input.update(&[touch(1, TouchPhase::Started, 10., 10.)], 0.);
+        let gestures = input.update(&[touch(1, TouchPhase::Stationary, 10., 10.)], 0.2);
+        assert_eq!(None, gestures.long_press);
+        let gestures = input.update(&[touch(1, TouchPhase::Stationary, 11., 10.)], 0.6);
+        assert_eq!(Some(vec2(10., 10.)), gestures.long_press);
+        let gestures = input.update(&[touch(1, TouchPhase::Stationary, 11., 10.)], 0.7);
+        assert_eq!(None, gestures.long_press);
+        // no tap when letting go
+        let gestures = input.update(&[touch(1, TouchPhase::Ended, 11., 10.)], 0.8);
+        assert_eq!(None, gestures.tap);
+    }
+
+    #[test]
+    fn pinch_zooms_in_steps() {
+        let mut input = TouchInput::default();
+        input.update(&[touch(1, TouchPhase::Started, 100., 100.)], 0.);
+        let fingers = |d: f32, phase| {
+            [
+                touch(1, TouchPhase::Stationary, 100., 100.),
+                touch(2, phase, 100. + d, 100.),
+            ]
+        };
+        assert_eq!(
+            0,
+            input.update(&fingers(40., TouchPhase::Started), 0.1).zoom
+        );
+        assert_eq!(0, input.update(&fingers(50., TouchPhase::Moved), 0.2).zoom);
+        assert_eq!(1, input.update(&fingers(60., TouchPhase::Moved), 0.3).zoom);
+        assert_eq!(0, input.update(&fingers(70., TouchPhase::Moved), 0.4).zoom);
+        assert_eq!(-1, input.update(&fingers(30., TouchPhase::Moved), 0.5).zoom);
+        // lifting the fingers after a pinch is no tap
+        let gestures = input.update(&fingers(30., TouchPhase::Ended), 0.6);
+        assert_eq!(None, gestures.tap);
+        let gestures = input.update(&[touch(1, TouchPhase::Ended, 100., 100.)], 0.7);
+        assert_eq!(None, gestures.tap);
+    }
+}
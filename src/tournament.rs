@@ -0,0 +1,213 @@
+/// `comfy-wars tournament`: plays seeded ai vs ai matches without a window
+/// and prints statistics, for balancing units and maps
+use crate::ai::{AiConfig, Controller};
+use crate::bot::{ExternalBot, DEFAULT_BOT_TIMEOUT};
+use crate::sim::*;
+use crate::util::Rng;
+use crate::*;
+use std::time::Duration;
+
+const USAGE: &str = "usage: comfy-wars tournament [--map <file.ldtk>] [--level <index>] \
+[--games <n>] [--seed <n>] [--max-rounds <n>] [--blue <ai>] [--red <ai>] \
+[--blue-bot <command>] [--red-bot <command>] [--bot-timeout <ms>]";
+
+#[derive(Debug)]
+pub struct TournamentArgs {
+    pub map: String,
+    pub level: usize,
+    pub games: u64,
+    pub seed: u64,
+    pub max_rounds: i32,
+    pub blue: AiConfig,
+    pub red: AiConfig,
+    /// external bots, the ai configs are their fallback
+    pub blue_bot: Option<String>,
+    pub red_bot: Option<String>,
+    pub bot_timeout: Duration,
+}
+
+impl Default for TournamentArgs {
+    fn default() -> Self {
+        Self {
+            map: "assets/comfy_wars.ldtk".to_string(),
+            level: 0,
+            games: 100,
+            seed: 0,
+            max_rounds: 50,
+            blue: AiConfig::default(),
+            red: AiConfig::default(),
+            blue_bot: None,
+            red_bot: None,
+            bot_timeout: DEFAULT_BOT_TIMEOUT,
+        }
+    }
+}
+
+impl TournamentArgs {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let mut result = Self::default();
+        let mut iter = args.iter();
+        while let Some(flag) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .ok_or_else(|| anyhow::anyhow!("missing value for {}\n{}", flag, USAGE))
+            };
+            match flag.as_str() {
+                "--map" => result.map = value()?.clone(),
+                "--level" => result.level = value()?.parse()?,
+                "--games" => result.games = value()?.parse()?,
+                "--seed" => result.seed = value()?.parse()?,
+                "--max-rounds" => result.max_rounds = value()?.parse()?,
+                "--blue" => result.blue = parse_ai(value()?)?,
+                "--red" => result.red = parse_ai(value()?)?,
+                "--blue-bot" => result.blue_bot = Some(value()?.clone()),
+                "--red-bot" => result.red_bot = Some(value()?.clone()),
+                "--bot-timeout" => result.bot_timeout = Duration::from_millis(value()?.parse()?),
+                _ => anyhow::bail!("unknown argument {}\n{}", flag, USAGE),
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn parse_ai(name: &str) -> Result<AiConfig> {
+    AiConfig::preset(name).ok_or_else(|| {
+        let names = AiConfig::presets().into_iter().map(|c| c.name).join(", ");
+        anyhow::anyhow!("unknown ai {}, available: {}", name, names)
+    })
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TournamentStats {
+    pub games: u64,
+    pub blue_wins: u64,
+    pub red_wins: u64,
+    pub draws: u64,
+    pub total_rounds: i64,
+    pub blue_losses: i64,
+    pub red_losses: i64,
+}
+
+impl TournamentStats {
+    pub fn record(&mut self, result: &MatchResult) {
+        self.games += 1;
+        match result.winner {
+            Some(Team::Blue) => self.blue_wins += 1,
+            Some(Team::Red) => self.red_wins += 1,
+            None => self.draws += 1,
+        }
+        self.total_rounds += result.rounds as i64;
+        self.blue_losses += result.blue_losses as i64;
+        self.red_losses += result.red_losses as i64;
+    }
+
+    fn percent(&self, n: u64) -> f64 {
+        100. * n as f64 / self.games.max(1) as f64
+    }
+
+    fn average(&self, n: i64) -> f64 {
+        n as f64 / self.games.max(1) as f64
+    }
+}
+
+fn controller(config: &AiConfig, bot: &Option<String>, timeout: Duration) -> Result<Controller> {
+    Ok(Controller {
+        config: config.clone(),
+        bot: bot
+            .as_ref()
+            .map(|command| ExternalBot::spawn(command, timeout))
+            .transpose()?,
+    })
+}
+
+pub fn run_tournament(args: &TournamentArgs, map: &GameMap) -> Result<TournamentStats> {
+    // bots are started once and play all games
+    let mut blue = controller(&args.blue, &args.blue_bot, args.bot_timeout)?;
+    let mut red = controller(&args.red, &args.red_bot, args.bot_timeout)?;
+    let mut stats = TournamentStats::default();
+    for game in 0..args.games {
+        let mut rng = Rng::new(args.seed.wrapping_add(game));
+        let m = Match::new(map.grids.clone(), map.actors.iter().cloned());
+        let result = m.run(&mut blue, &mut red, &mut rng, args.max_rounds);
+        stats.record(&result);
+    }
+    Ok(stats)
+}
+
+/// entry point of the subcommand
+pub fn tournament_main(args: &[String]) -> Result<()> {
+    let args = TournamentArgs::parse(args)?;
+    let map = GameMap::load_ldtk(&args.map, args.level)?;
+    let stats = run_tournament(&args, &map)?;
+
+    println!("map: {} (level {})", args.map, args.level);
+    println!(
+        "{} games, seeds {}..{}, blue: {}, red: {}",
+        stats.games,
+        args.seed,
+        args.seed.wrapping_add(args.games),
+        args.blue_bot.as_ref().unwrap_or(&args.blue.name),
+        args.red_bot.as_ref().unwrap_or(&args.red.name)
+    );
+    println!(
+        "blue wins: {:>5} ({:.1}%)",
+        stats.blue_wins,
+        stats.percent(stats.blue_wins)
+    );
+    println!(
+        "red wins:  {:>5} ({:.1}%)",
+        stats.red_wins,
+        stats.percent(stats.red_wins)
+    );
+    println!(
+        "draws:     {:>5} ({:.1}%)",
+        stats.draws,
+        stats.percent(stats.draws)
+    );
+    println!("average rounds: {:.2}", stats.average(stats.total_rounds));
+    println!(
+        "average losses: blue {:.2}, red {:.2}",
+        stats.average(stats.blue_losses),
+        stats.average(stats.red_losses)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_args() {
+        let parsed =
+            TournamentArgs::parse(&args("--games 10 --seed 3 --red focus --red-bot ./bot"))
+                .unwrap();
+        assert_eq!(10, parsed.games);
+        assert_eq!(3, parsed.seed);
+        assert_eq!("default", parsed.blue.name);
+        assert_eq!("focus", parsed.red.name);
+        assert_eq!(Some("./bot".to_string()), parsed.red_bot);
+
+        assert!(TournamentArgs::parse(&args("--red nonsense")).is_err());
+        assert!(TournamentArgs::parse(&args("--games")).is_err());
+        assert!(TournamentArgs::parse(&args("--frobnicate 1")).is_err());
+    }
+
+    #[test]
+    fn bundled_map_plays_to_the_end() {
+        let map = GameMap::load_ldtk(&TournamentArgs::default().map, 0).unwrap();
+        assert_eq!(6, map.actors.len());
+
+        let args = TournamentArgs {
+            games: 4,
+            ..Default::default()
+        };
+        let stats = run_tournament(&args, &map).unwrap();
+        assert_eq!(4, stats.games);
+        assert_eq!(4, stats.blue_wins + stats.red_wins + stats.draws);
+    }
+}
@@ -18,6 +18,22 @@ pub struct IVec2Proxy {
     pub y: i32,
 }
 
+pub mod option_ivec2_proxy {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Helper(#[serde(with = "IVec2Proxy")] IVec2);
+
+    pub fn serialize<S: Serializer>(v: &Option<IVec2>, s: S) -> Result<S::Ok, S::Error> {
+        v.map(Helper).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<IVec2>, D::Error> {
+        Ok(Option::<Helper>::deserialize(d)?.map(|Helper(v)| v))
+    }
+}
+
 // needed because orphan rules are annoying
 #[derive(
     Default,
@@ -56,11 +72,44 @@ impl From<Vec2> for Vec2f {
     }
 }
 
-impl Into<Vec2> for Vec2f {
-    fn into(self) -> Vec2 {
+impl From<Vec2f> for Vec2 {
+    fn from(value: Vec2f) -> Self {
         Vec2 {
-            x: self.x,
-            y: self.y,
+            x: value.x,
+            y: value.y,
+        }
+    }
+}
+
+/// small deterministic rng (splitmix64), so seeded runs are reproducible
+/// on every platform without pulling in a dependency
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// returns a number in `0..n`
+    pub fn below(&mut self, n: usize) -> usize {
+        assert!(n > 0);
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn shuffle<T>(&mut self, v: &mut [T]) {
+        for i in (1..v.len()).rev() {
+            v.swap(i, self.below(i + 1));
         }
     }
 }
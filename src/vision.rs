@@ -0,0 +1,209 @@
+/// line of sight and field of view on grids
+/// `blocks` decides which tile values can't be seen through, e.g. forests
+/// a blocking tile is visible itself, only the tiles behind it are hidden
+use crate::grids::Grid;
+use macroquad::math::{ivec2, IVec2};
+
+/// tiles on the bresenham line from `from` to `to`, both inclusive
+pub fn line(from: IVec2, to: IVec2) -> Vec<IVec2> {
+    let d = (to - from).abs();
+    let step = ivec2((to.x - from.x).signum(), (to.y - from.y).signum());
+    let mut err = d.x - d.y;
+    let mut pos = from;
+    let mut result = Vec::with_capacity((d.x.max(d.y) + 1) as usize);
+    loop {
+        result.push(pos);
+        if pos == to {
+            return result;
+        }
+        let e2 = 2 * err;
+        if e2 > -d.y {
+            err -= d.y;
+            pos.x += step.x;
+        }
+        if e2 < d.x {
+            err += d.x;
+            pos.y += step.y;
+        }
+    }
+}
+
+/// transforms from octant local (column, row) to grid offsets
+const OCTANTS: [(i32, i32, i32, i32); 8] = [
+    (1, 0, 0, 1),
+    (0, 1, 1, 0),
+    (0, -1, 1, 0),
+    (-1, 0, 0, 1),
+    (-1, 0, 0, -1),
+    (0, -1, -1, 0),
+    (0, 1, -1, 0),
+    (1, 0, 0, -1),
+];
+
+impl<T: Clone> Grid<T> {
+    /// true if no tile strictly between `from` and `to` blocks sight
+    pub fn line_of_sight(&self, from: IVec2, to: IVec2, blocks: impl Fn(&T) -> bool) -> bool {
+        if !self.is_valid(from) || !self.is_valid(to) {
+            return false;
+        }
+        let line = line(from, to);
+        // nothing in between, e.g. looking at its own tile
+        if line.len() <= 2 {
+            return true;
+        }
+        line[1..line.len() - 1]
+            .iter()
+            .all(|pos| !blocks(&self[*pos]))
+    }
+
+    /// tiles visible from `origin` within `radius` (euclidean), using recursive shadowcasting
+    pub fn field_of_view(
+        &self,
+        origin: IVec2,
+        radius: i32,
+        blocks: impl Fn(&T) -> bool,
+    ) -> Grid<bool> {
+        let mut visible = Grid::new(self.width, self.height, false);
+        if !self.is_valid(origin) {
+            return visible;
+        }
+        visible[origin] = true;
+        for octant in OCTANTS {
+            self.cast_light(&mut visible, origin, radius, 1, 1., 0., octant, &blocks);
+        }
+        visible
+    }
+
+    /// scans one octant row by row, `start` and `end` are the slopes still lit
+    #[allow(clippy::too_many_arguments)]
+    fn cast_light(
+        &self,
+        visible: &mut Grid<bool>,
+        origin: IVec2,
+        radius: i32,
+        row: i32,
+        mut start: f32,
+        end: f32,
+        (xx, xy, yx, yy): (i32, i32, i32, i32),
+        blocks: &impl Fn(&T) -> bool,
+    ) {
+        if start < end {
+            return;
+        }
+        let mut next_start = start;
+        for j in row..=radius {
+            let dy = -j;
+            let mut blocked = false;
+            for dx in -j..=0 {
+                let left_slope = (dx as f32 - 0.5) / (dy as f32 + 0.5);
+                let right_slope = (dx as f32 + 0.5) / (dy as f32 - 0.5);
+                if start < right_slope {
+                    continue;
+                }
+                if end > left_slope {
+                    break;
+                }
+                let pos = ivec2(origin.x + dx * xx + dy * xy, origin.y + dx * yx + dy * yy);
+                // outside of the map counts as a wall
+                let opaque = !self.is_valid(pos) || blocks(&self[pos]);
+                if self.is_valid(pos) && dx * dx + dy * dy <= radius * radius {
+                    visible[pos] = true;
+                }
+                if blocked {
+                    if opaque {
+                        next_start = right_slope;
+                        continue;
+                    }
+                    blocked = false;
+                    start = next_start;
+                } else if opaque && j < radius {
+                    blocked = true;
+                    let octant = (xx, xy, yx, yy);
+                    self.cast_light(
+                        visible,
+                        origin,
+                        radius,
+                        j + 1,
+                        start,
+                        left_slope,
+                        octant,
+                        blocks,
+                    );
+                    next_start = right_slope;
+                }
+            }
+            if blocked {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_endpoints() {
+        assert_eq!(vec![ivec2(0, 0)], line(ivec2(0, 0), ivec2(0, 0)));
+        let l = line(ivec2(0, 0), ivec2(5, 2));
+        assert_eq!(6, l.len());
+        assert_eq!(ivec2(5, 2), *l.last().unwrap());
+        let back = line(ivec2(3, 4), ivec2(-2, 1));
+        assert_eq!(ivec2(3, 4), back[0]);
+        assert_eq!(ivec2(-2, 1), *back.last().unwrap());
+        // every step moves to a neighboring tile
+        for (a, b) in back.iter().zip(back.iter().skip(1)) {
+            assert!((*b - *a).abs().max_element() == 1);
+        }
+    }
+
+    #[test]
+    fn line_of_sight_through_walls() {
+        let mut grid = Grid::new(7, 3, false);
+        grid[(3, 1)] = true;
+        let blocks = |b: &bool| *b;
+        assert!(!grid.line_of_sight(ivec2(0, 1), ivec2(6, 1), blocks));
+        // the wall itself can be seen
+        assert!(grid.line_of_sight(ivec2(0, 1), ivec2(3, 1), blocks));
+        assert!(grid.line_of_sight(ivec2(0, 0), ivec2(6, 0), blocks));
+        assert!(!grid.line_of_sight(ivec2(0, 0), ivec2(7, 0), blocks));
+    }
+
+    #[test]
+    fn line_of_sight_to_the_same_tile() {
+        let mut grid = Grid::new(3, 3, false);
+        grid[(1, 1)] = true;
+        let blocks = |b: &bool| *b;
+        assert!(grid.line_of_sight(ivec2(1, 1), ivec2(1, 1), blocks));
+        assert!(grid.line_of_sight(ivec2(0, 0), ivec2(0, 0), blocks));
+        assert!(grid.line_of_sight(ivec2(0, 0), ivec2(1, 1), blocks));
+    }
+
+    #[test]
+    fn open_field_is_a_circle() {
+        let grid = Grid::new(11, 11, false);
+        let fov = grid.field_of_view(ivec2(5, 5), 3, |b| *b);
+        for (pos, seen) in fov.iter_coords() {
+            let d = pos - ivec2(5, 5);
+            assert_eq!(d.x * d.x + d.y * d.y <= 9, *seen, "{}", pos);
+        }
+    }
+
+    #[test]
+    fn walls_cast_shadows() {
+        let mut grid = Grid::new(9, 7, false);
+        for y in 0..7 {
+            grid[(3, y)] = true;
+        }
+        grid[(3, 3)] = false;
+        let fov = grid.field_of_view(ivec2(1, 3), 10, |b| *b);
+        // through the gap
+        assert!(fov[(6, 3)]);
+        // the wall is visible, the tiles behind it mostly not
+        assert!(fov[(3, 0)]);
+        assert!(!fov[(5, 0)]);
+        assert!(!fov[(8, 6)]);
+        assert!(fov[(0, 0)]);
+    }
+}